@@ -0,0 +1,598 @@
+use crate::config;
+use crate::db::*;
+use crate::model::*;
+use crate::system;
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct WireguardFile {
+    pub private_key: Option<String>,
+    pub listen_port: Option<u16>,
+    pub mtu: Option<u16>,
+    pub public_key: Option<String>,
+    pub endpoint: Option<String>,
+    pub persistent_keepalive: Option<u16>,
+    pub allowed_ips: Vec<String>,
+    pub peer_sections: usize,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BirdPeerFile {
+    pub protocol: String,
+    pub source_address: Option<String>,
+    pub neighbor: String,
+    pub interface_name: String,
+    pub asn: u64,
+    pub disabled: bool,
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+pub fn parse_wireguard_config(content: &str) -> WireguardFile {
+    let mut file = WireguardFile::default();
+    let mut in_peer = false;
+    for line in content.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_peer = line.eq_ignore_ascii_case("[Peer]");
+            if in_peer {
+                file.peer_sections += 1;
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+        match (in_peer, key.as_str()) {
+            (false, "privatekey") => file.private_key = Some(value),
+            (false, "listenport") => file.listen_port = value.parse().ok(),
+            (false, "mtu") => file.mtu = value.parse().ok(),
+            (true, "publickey") => file.public_key = Some(value),
+            (true, "endpoint") => file.endpoint = Some(value),
+            (true, "persistentkeepalive") => file.persistent_keepalive = value.parse().ok(),
+            (true, "allowedips") => file.allowed_ips.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|cidr| !cidr.is_empty())
+                    .map(str::to_string),
+            ),
+            _ => {}
+        }
+    }
+    file
+}
+
+// 解析 `neighbor <addr> % '<iface>' as <asn>;` 一行，引号可省略
+fn parse_neighbor(statement: &str) -> Option<(String, String, u64)> {
+    let rest = statement.strip_prefix("neighbor")?.trim();
+    let (target, asn) = rest.rsplit_once(" as ")?;
+    let asn = asn.trim().parse().ok()?;
+    let (addr, iface) = target.split_once('%')?;
+    let iface = iface.trim().trim_matches(|c| c == '\'' || c == '"');
+    Some((addr.trim().to_string(), iface.to_string(), asn))
+}
+
+pub fn parse_bird_peer_config(content: &str) -> Result<BirdPeerFile, String> {
+    let mut protocols = Vec::new();
+    let mut current: Option<BirdPeerFile> = None;
+    let text: String = content
+        .lines()
+        .map(strip_comment)
+        .collect::<Vec<&str>>()
+        .join("\n");
+    for statement in text.split([';', '{', '}']) {
+        let statement = statement
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if let Some(rest) = statement.strip_prefix("protocol bgp ") {
+            if let Some(done) = current.take() {
+                protocols.push(done);
+            }
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            current = Some(BirdPeerFile {
+                protocol: name.to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let Some(protocol) = current.as_mut() else {
+            continue;
+        };
+        if let Some(addr) = statement.strip_prefix("source address ") {
+            protocol.source_address = Some(addr.trim().to_string());
+        } else if statement.starts_with("neighbor ") {
+            let (addr, iface, asn) = parse_neighbor(&statement)
+                .ok_or_else(|| format!("Cannot parse neighbor statement: {}", statement))?;
+            protocol.neighbor = addr;
+            protocol.interface_name = iface;
+            protocol.asn = asn;
+        } else if statement == "disabled"
+            || statement == "disabled yes"
+            || statement == "disabled on"
+        {
+            protocol.disabled = true;
+        }
+    }
+    if let Some(done) = current.take() {
+        protocols.push(done);
+    }
+
+    match protocols.len() {
+        0 => Err("No BGP protocol found".to_string()),
+        1 => {
+            let protocol = protocols.remove(0);
+            if protocol.asn == 0 {
+                Err(format!(
+                    "No neighbor found in protocol {}",
+                    protocol.protocol
+                ))
+            } else {
+                Ok(protocol)
+            }
+        }
+        _ => Err(format!(
+            "Found {} BGP protocols, expected exactly one",
+            protocols.len()
+        )),
+    }
+}
+
+// 返回目录下以 prefix 开头的 .conf 文件的接口名与完整路径，按路径排序
+fn list_conf_files(dir: &str, prefix: &str) -> Result<Vec<(String, String)>, String> {
+    let names = system::list_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir, e))?;
+    let mut files: Vec<(String, String)> = names
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .filter_map(|name| name.strip_suffix(".conf"))
+        .map(|stem| {
+            (
+                stem.to_string(),
+                format!("{}/{}.conf", dir.trim_end_matches('/'), stem),
+            )
+        })
+        .collect();
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+fn unmapped(
+    file: &str,
+    asn: Option<u64>,
+    interface_name: Option<String>,
+    reason: String,
+) -> AdoptResult {
+    AdoptResult {
+        file: file.to_string(),
+        asn,
+        interface_name,
+        outcome: "unmapped".to_string(),
+        messages: vec![reason],
+    }
+}
+
+// 扫描已有的 WireGuard 与 BIRD 配置，将能够识别的 peer 写入数据库，不会改动运行中的隧道
+pub fn adopt_existing_peers(
+    conn: &Connection,
+    wg_dir: &str,
+    bird_dir: &str,
+    dry_run: bool,
+) -> Result<AdoptReport, String> {
+    let config = config();
+    let mut results = Vec::new();
+
+    let mut bird_files: HashMap<String, (String, BirdPeerFile)> = HashMap::new();
+    for (_, path) in list_conf_files(bird_dir, "")? {
+        let parsed = system::read_file(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| parse_bird_peer_config(&content));
+        match parsed {
+            Ok(bird) => {
+                bird_files.insert(bird.interface_name.clone(), (path, bird));
+            }
+            Err(reason) => results.push(unmapped(&path, None, None, reason)),
+        }
+    }
+
+    for (interface_name, wg_path) in list_conf_files(wg_dir, &config.peer.interface_prefix)? {
+        let wg = match system::read_file(&wg_path) {
+            Ok(content) => parse_wireguard_config(&content),
+            Err(e) => {
+                results.push(unmapped(
+                    &wg_path,
+                    None,
+                    Some(interface_name),
+                    e.to_string(),
+                ));
+                continue;
+            }
+        };
+        let Some((bird_path, bird)) = bird_files.remove(&interface_name) else {
+            results.push(unmapped(
+                &wg_path,
+                None,
+                Some(interface_name),
+                "No BIRD peer config found for this interface".to_string(),
+            ));
+            continue;
+        };
+        if wg.peer_sections != 1 {
+            results.push(unmapped(
+                &wg_path,
+                Some(bird.asn),
+                Some(interface_name),
+                format!(
+                    "Found {} [Peer] sections, expected exactly one",
+                    wg.peer_sections
+                ),
+            ));
+            continue;
+        }
+
+        let mut peer = Peer {
+            asn: bird.asn,
+            wireguard_endpoint: wg.endpoint.clone().unwrap_or_default(),
+            wireguard_link_local: bird.neighbor.clone(),
+            wireguard_public_key: wg.public_key.clone().unwrap_or_default(),
+            mtu: wg.mtu,
+            persistent_keepalive: wg.persistent_keepalive,
+            extra_allowed_ips: wg
+                .allowed_ips
+                .iter()
+                .filter(|cidr| !config.peer.allowed_ips.contains(cidr))
+                .cloned()
+                .collect(),
+            contact: String::new(),
+            notes: "Adopted from existing configuration".to_string(),
+        };
+        if interface_name != peer.gen_interface_name() {
+            results.push(unmapped(
+                &wg_path,
+                Some(peer.asn),
+                Some(interface_name),
+                format!(
+                    "Interface name does not match the expected {}",
+                    peer.gen_interface_name()
+                ),
+            ));
+            continue;
+        }
+        if let Err(reason) = peer.validate() {
+            results.push(unmapped(
+                &wg_path,
+                Some(peer.asn),
+                Some(interface_name),
+                reason,
+            ));
+            continue;
+        }
+
+        // 之后 /update 或 /resync 会按本程序的规则重新生成配置，
+        // 这些字段不一致时重新生成会中断已有的会话，因此不接管
+        let mut conflicts = Vec::new();
+        if bird.protocol != interface_name {
+            conflicts.push(format!(
+                "BIRD protocol name {} differs from the interface name",
+                bird.protocol
+            ));
+        }
+        match peer.gen_listen_port() {
+            Ok(port) if wg.listen_port == Some(port) => {}
+            Ok(port) => conflicts.push(format!(
+                "ListenPort {:?} differs from the generated {}",
+                wg.listen_port, port
+            )),
+            Err(e) => conflicts.push(e.to_string()),
+        }
+        if wg.private_key.as_deref() != Some(config.peer.wireguard_private_key.as_str()) {
+            conflicts.push("PrivateKey differs from Peer.wireguard_private_key".to_string());
+        }
+        if bird.source_address.as_deref() != Some(config.peer.link_local.as_str()) {
+            conflicts.push(format!(
+                "BIRD source address {:?} differs from Peer.link_local",
+                bird.source_address
+            ));
+        }
+        if !conflicts.is_empty() {
+            results.push(AdoptResult {
+                file: wg_path,
+                asn: Some(peer.asn),
+                interface_name: Some(interface_name),
+                outcome: "unmapped".to_string(),
+                messages: conflicts,
+            });
+            continue;
+        }
+
+        // 重新生成时只会补上缺少的部分，仅作提示
+        let mut messages = Vec::new();
+        if let Some(missing) = config
+            .peer
+            .allowed_ips
+            .iter()
+            .find(|cidr| !wg.allowed_ips.contains(cidr))
+        {
+            messages.push(format!("AllowedIPs does not contain {}", missing));
+        }
+
+        let exists = match get_peer_by_asn(conn, peer.asn) {
+            Ok(_) => true,
+            Err(PeerDbError::NotFound) => false,
+            Err(e) => return Err(e.to_string()),
+        };
+        let outcome = if exists {
+            "already_managed"
+        } else if dry_run {
+            "would_adopt"
+        } else {
+            let state = if bird.disabled {
+                PeerState::Disabled
+            } else {
+                PeerState::Active
+            };
+            adopt_peer(conn, &peer, state, &wg_path, &bird_path).map_err(|e| e.to_string())?;
+            "adopted"
+        };
+        results.push(AdoptResult {
+            file: wg_path,
+            asn: Some(peer.asn),
+            interface_name: Some(interface_name),
+            outcome: outcome.to_string(),
+            messages,
+        });
+    }
+
+    let mut leftover: Vec<(String, BirdPeerFile)> = bird_files.into_values().collect();
+    leftover.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, bird) in leftover {
+        results.push(unmapped(
+            &path,
+            Some(bird.asn),
+            Some(bird.interface_name),
+            "No WireGuard config found for this interface".to_string(),
+        ));
+    }
+
+    Ok(AdoptReport { dry_run, results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_config::{gen_bird_config, gen_wireguard_config};
+    use crate::testing::Harness;
+
+    fn deployed_peer(asn: u64, state: PeerState) -> Peer {
+        let peer = Peer {
+            asn,
+            wireguard_endpoint: "peer.example.net:51820".to_string(),
+            wireguard_link_local: "fe80::1".to_string(),
+            wireguard_public_key: "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=".to_string(),
+            mtu: None,
+            persistent_keepalive: None,
+            extra_allowed_ips: Vec::new(),
+            contact: String::new(),
+            notes: String::new(),
+        };
+        system::save_config(
+            &peer.gen_wireguard_config_path(),
+            &gen_wireguard_config(&peer).unwrap(),
+            &peer.gen_bird_config_path(),
+            &gen_bird_config(&peer, state).unwrap(),
+        )
+        .unwrap();
+        peer
+    }
+
+    fn outcomes(report: &AdoptReport) -> Vec<(Option<u64>, &str)> {
+        report
+            .results
+            .iter()
+            .map(|result| (result.asn, result.outcome.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_adopt_existing_peers() {
+        let h = Harness::setup();
+        deployed_peer(4242420253, PeerState::Active);
+        deployed_peer(4242420254, PeerState::Disabled);
+        let config = config();
+        let conn = h.db.lock().unwrap();
+        let adopt = |dry_run| {
+            adopt_existing_peers(
+                &conn,
+                &config.paths.wireguard_dir,
+                &config.paths.bird_peers_dir,
+                dry_run,
+            )
+            .unwrap()
+        };
+
+        let report = adopt(true);
+        assert_eq!(
+            outcomes(&report),
+            [
+                (Some(4242420253), "would_adopt"),
+                (Some(4242420254), "would_adopt")
+            ]
+        );
+        assert!(matches!(
+            get_peer_by_asn(&conn, 4242420253),
+            Err(PeerDbError::NotFound)
+        ));
+
+        let report = adopt(false);
+        assert_eq!(
+            outcomes(&report),
+            [(Some(4242420253), "adopted"), (Some(4242420254), "adopted")]
+        );
+        assert!(
+            report
+                .results
+                .iter()
+                .all(|result| result.messages.is_empty())
+        );
+        assert_eq!(
+            get_peer_by_asn(&conn, 4242420254).unwrap().state,
+            PeerState::Disabled
+        );
+        // 只读取配置，不执行任何命令
+        assert!(h.backend.commands().is_empty());
+
+        let report = adopt(false);
+        assert_eq!(
+            outcomes(&report),
+            [
+                (Some(4242420253), "already_managed"),
+                (Some(4242420254), "already_managed")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adopt_refuses_conflicts() {
+        let h = Harness::setup();
+        let tamper = |path: &str, from: &str, to: &str| {
+            let content = h.backend.file(path).unwrap();
+            assert!(content.contains(from), "{}", content);
+            std::fs::write(h.backend.host_path(path), content.replace(from, to)).unwrap();
+        };
+
+        let peer = deployed_peer(4242420253, PeerState::Active);
+        let port = peer.gen_listen_port().unwrap();
+        tamper(
+            &peer.gen_wireguard_config_path(),
+            &format!("ListenPort = {}", port),
+            "ListenPort = 51820",
+        );
+        let peer = deployed_peer(4242420254, PeerState::Active);
+        tamper(
+            &peer.gen_wireguard_config_path(),
+            crate::testing::TEST_PRIVATE_KEY,
+            "cHJpdmF0ZQ==",
+        );
+        let peer = deployed_peer(4242420255, PeerState::Active);
+        tamper(
+            &peer.gen_bird_config_path(),
+            "protocol bgp dn42_0255",
+            "protocol bgp legacy_0255",
+        );
+
+        let config = config();
+        let conn = h.db.lock().unwrap();
+        let report = adopt_existing_peers(
+            &conn,
+            &config.paths.wireguard_dir,
+            &config.paths.bird_peers_dir,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            outcomes(&report),
+            [
+                (Some(4242420253), "unmapped"),
+                (Some(4242420254), "unmapped"),
+                (Some(4242420255), "unmapped")
+            ]
+        );
+        let messages: Vec<&str> = report
+            .results
+            .iter()
+            .map(|result| result.messages[0].as_str())
+            .collect();
+        assert!(messages[0].starts_with("ListenPort"), "{:?}", messages);
+        assert!(messages[1].starts_with("PrivateKey"), "{:?}", messages);
+        assert!(messages[2].contains("legacy_0255"), "{:?}", messages);
+        for asn in [4242420253, 4242420254, 4242420255] {
+            assert!(matches!(
+                get_peer_by_asn(&conn, asn),
+                Err(PeerDbError::NotFound)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_wireguard_config() {
+        let wg = parse_wireguard_config(
+            "[Interface]
+PrivateKey = cHJpdmF0ZQ==
+ListenPort = 20253
+MTU = 1400 # PPPoE
+Table = off
+
+[Peer]
+PublicKey = AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+Endpoint = peer.example.net:51820
+AllowedIPs = 10.0.0.0/8, fd00::/8
+AllowedIPs = fe80::/64
+PersistentKeepalive = 25
+",
+        );
+        assert_eq!(wg.private_key.as_deref(), Some("cHJpdmF0ZQ=="));
+        assert_eq!(wg.listen_port, Some(20253));
+        assert_eq!(wg.mtu, Some(1400));
+        assert_eq!(
+            wg.public_key.as_deref(),
+            Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=")
+        );
+        assert_eq!(wg.endpoint.as_deref(), Some("peer.example.net:51820"));
+        assert_eq!(wg.allowed_ips, vec!["10.0.0.0/8", "fd00::/8", "fe80::/64"]);
+        assert_eq!(wg.persistent_keepalive, Some(25));
+        assert_eq!(wg.peer_sections, 1);
+    }
+
+    #[test]
+    fn test_parse_bird_peer_config() {
+        let bird = parse_bird_peer_config(
+            "# hand-made
+protocol bgp dn42_0253 from dnpeers {
+    source address fe80::1234;
+    neighbor fe80::253 % 'dn42_0253' as 4242420253;
+    disabled;
+    ipv4 {
+        extended next hop on;
+    };
+};",
+        )
+        .unwrap();
+        assert_eq!(bird.protocol, "dn42_0253");
+        assert_eq!(bird.source_address.as_deref(), Some("fe80::1234"));
+        assert_eq!(bird.neighbor, "fe80::253");
+        assert_eq!(bird.interface_name, "dn42_0253");
+        assert_eq!(bird.asn, 4242420253);
+        assert!(bird.disabled);
+
+        let bird = parse_bird_peer_config(
+            "protocol bgp foo from dnpeers { neighbor fe80::1%dn42_1234 as 4242421234; }",
+        )
+        .unwrap();
+        assert_eq!(bird.interface_name, "dn42_1234");
+        assert!(!bird.disabled);
+    }
+
+    #[test]
+    fn test_parse_bird_peer_config_errors() {
+        assert!(parse_bird_peer_config("").is_err());
+        assert!(parse_bird_peer_config("protocol bgp a from dnpeers { };").is_err());
+        assert!(
+            parse_bird_peer_config(
+                "protocol bgp a_v4 { neighbor 172.20.0.1 % 'a' as 4242420001; };
+                 protocol bgp a_v6 { neighbor fe80::1 % 'a' as 4242420001; };"
+            )
+            .is_err()
+        );
+    }
+}
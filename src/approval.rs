@@ -0,0 +1,51 @@
+use crate::config;
+use crate::model::*;
+use crate::system::run_check;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+fn check_registry(peer: &Peer) -> Result<(), String> {
+    let aut_num = Path::new(&config().approval.registry_path)
+        .join("data/aut-num")
+        .join(format!("AS{}", peer.asn));
+    if aut_num.is_file() {
+        Ok(())
+    } else {
+        Err(format!("AS{} not found in registry", peer.asn))
+    }
+}
+
+fn check_endpoint_reachable(peer: &Peer) -> Result<(), String> {
+    let addr = peer
+        .wireguard_endpoint
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("Endpoint {} does not resolve", peer.wireguard_endpoint))?;
+    let ip = addr.ip().to_string();
+    let reachable = run_check(
+        &config().approval.ping_path,
+        &["-c", "1", "-W", "2", ip.as_str()],
+    )
+    .map_err(|e| format!("Failed to run ping: {}", e))?;
+    if reachable {
+        Ok(())
+    } else {
+        Err(format!("Endpoint {} is not reachable", ip))
+    }
+}
+
+// 检查 pending 请求是否满足自动批准规则，返回未通过的原因
+pub fn auto_approve_check(peer: &Peer) -> Result<(), String> {
+    let config = config();
+    if !config.approval.auto_approve {
+        return Err("Auto-approval is disabled".to_string());
+    }
+    if !config.approval.registry_path.is_empty() {
+        check_registry(peer)?;
+    }
+    if config.approval.check_endpoint_reachable {
+        check_endpoint_reachable(peer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use crate::Db;
+use crate::db::*;
+use crate::model::*;
+use http_types::{Request, Response, StatusCode};
+use serde_json::Value;
+use std::future::Future;
+use std::net::SocketAddr;
+
+const REDACTED_KEYS: [&str; 4] = ["secret", "private_key", "password", "token"];
+
+// 审计日志中不保存任何密钥类字段
+pub fn redact_payload(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if REDACTED_KEYS.iter().any(|k| key.contains(k)) {
+                    *item = Value::String("[REDACTED]".to_string());
+                } else {
+                    redact_payload(item);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_payload),
+        _ => {}
+    }
+}
+
+pub fn request_principal(req: &Request) -> String {
+    req.ext()
+        .get::<Principal>()
+        .map(|p| p.0.clone())
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+pub fn request_source_ip(req: &Request) -> Option<String> {
+    req.peer_addr()
+        .map(|addr| match addr.parse::<SocketAddr>() {
+            Ok(sock_addr) => sock_addr.ip().to_string(),
+            Err(_) => addr.to_string(),
+        })
+}
+
+// 执行会修改 peer 的请求，并将请求内容与处理结果写入审计日志
+pub async fn audited<F, Fut>(
+    action: &str,
+    mut req: Request,
+    db: Db,
+    handler: F,
+) -> http_types::Result<Response>
+where
+    F: FnOnce(Request, Db) -> Fut,
+    Fut: Future<Output = http_types::Result<Response>>,
+{
+    let body = req.body_string().await?;
+    let mut payload = if body.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&body).unwrap_or_else(|_| Value::String(body.clone()))
+    };
+    let asn = payload
+        .get("asn")
+        .and_then(Value::as_u64)
+        .or_else(|| crate::rest::path_asn(req.url().path()));
+    redact_payload(&mut payload);
+    let principal = request_principal(&req);
+    let source_ip = request_source_ip(&req);
+    req.set_body(body);
+
+    let mut res = handler(req, db.clone()).await?;
+
+    let content_type = res.content_type();
+    let outcome = res.body_string().await?;
+    res.set_body(outcome.clone());
+    if let Some(mime) = content_type {
+        res.set_content_type(mime);
+    }
+
+    let action = action.to_string();
+    let status = res.status() as u16;
+    let db_result = smol::unblock(move || match db.lock() {
+        Ok(conn) => insert_audit(
+            &conn,
+            &action,
+            asn,
+            &principal,
+            source_ip.as_deref(),
+            &payload,
+            status,
+            &outcome,
+        ),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = db_result {
+        eprintln!("Failed to write audit log: {}", e);
+    }
+
+    Ok(res)
+}
+
+// 命令行操作的执行者，经 sudo 执行时记录原始用户
+pub fn cli_principal() -> String {
+    let user = std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("cli:{}", user)
+}
+
+// 将命令行发起的修改操作写入审计日志
+pub async fn record_cli_action(
+    db: &Db,
+    action: &str,
+    asn: Option<u64>,
+    mut payload: Value,
+    status: StatusCode,
+    outcome: &str,
+) {
+    redact_payload(&mut payload);
+    let db = db.clone();
+    let action = action.to_string();
+    let outcome = outcome.to_string();
+    let db_result = smol::unblock(move || match db.lock() {
+        Ok(conn) => insert_audit(
+            &conn,
+            &action,
+            asn,
+            &cli_principal(),
+            None,
+            &payload,
+            status as u16,
+            &outcome,
+        ),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = db_result {
+        eprintln!("Failed to write audit log: {}", e);
+    }
+}
+
+// 异步任务执行完成后，以提交请求的身份记录实际结果
+pub async fn record_job_result(db: &Db, job: &Job, status: StatusCode, outcome: &str) {
+    let mut payload = serde_json::from_str(&job.payload).unwrap_or(Value::Null);
+    redact_payload(&mut payload);
+    let db = db.clone();
+    let action = job.action.as_str();
+    let asn = job.asn;
+    let principal = job.principal.clone();
+    let source_ip = job.source_ip.clone();
+    let outcome = format!("Job {}: {}", job.id, outcome);
+    let db_result = smol::unblock(move || match db.lock() {
+        Ok(conn) => insert_audit(
+            &conn,
+            action,
+            asn,
+            &principal,
+            source_ip.as_deref(),
+            &payload,
+            status as u16,
+            &outcome,
+        ),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = db_result {
+        eprintln!("Failed to write audit log: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_payload() {
+        let mut payload = json!({
+            "asn": 4242420253u64,
+            "wireguard_public_key": "public",
+            "api_secret": "s",
+            "nested": [{"Private_Key": "k"}],
+        });
+        redact_payload(&mut payload);
+        assert_eq!(payload["asn"], 4242420253u64);
+        assert_eq!(payload["wireguard_public_key"], "public");
+        assert_eq!(payload["api_secret"], "[REDACTED]");
+        assert_eq!(payload["nested"][0]["Private_Key"], "[REDACTED]");
+    }
+}
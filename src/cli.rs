@@ -0,0 +1,350 @@
+use crate::Db;
+use crate::audit::*;
+use crate::db::*;
+use crate::gen_config::*;
+use crate::handler::*;
+use crate::model::*;
+use crate::system::BirdReload;
+use crate::{
+    CONFIG_PATH_OVERRIDE, DB_PATH_OVERRIDE, config_path, load_and_check_config, open_database,
+    serve, set_config,
+};
+use http_types::StatusCode;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "Usage: dn42-autopeer-daemon [--config <PATH>] [--db <PATH>] [COMMAND]
+
+Options:
+  --config <PATH>       Config file (default: $DN42_AUTOPEER_CONFIG or ./config.toml)
+  --db <PATH>           Database file (default: $DN42_AUTOPEER_DB or Paths.database)
+
+Commands:
+  serve                 Run the HTTP API server (default)
+  add --asn <ASN> --endpoint <HOST:PORT> --link-local <ADDR> --public-key <KEY>
+      [--mtu <MTU>] [--keepalive <SECONDS>] [--allowed-ip <CIDR>]...
+      [--contact <CONTACT>] [--notes <NOTES>]
+                        Add and deploy a peer
+  del <ASN>             Remove a peer
+  get <ASN>             Print a peer as JSON
+  list [--state <STATE>]
+                        List peers
+  reconcile             Re-render all configs and bring tunnels and BIRD in line with the database
+  check-config          Validate the configuration file
+  render <ASN>          Print the WireGuard and BIRD configs of a peer without writing them
+  migrate               Apply pending database migrations
+  help                  Show this message";
+
+// 解析 `--key value` 形式的参数，同一个 key 可以出现多次
+fn parse_flags(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut flags = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let Some(key) = arg.strip_prefix("--") else {
+            return Err(format!("Unexpected argument: {}", arg));
+        };
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("Missing value for --{}", key))?;
+        flags.push((key.to_string(), value.clone()));
+    }
+    Ok(flags)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", name, value))
+}
+
+fn parse_asn_arg(args: &[String]) -> Result<u64, String> {
+    match args {
+        [asn] => parse_number("ASN", asn),
+        _ => Err("Expected exactly one ASN argument".to_string()),
+    }
+}
+
+fn parse_add_args(args: &[String]) -> Result<Peer, String> {
+    let mut peer = Peer {
+        asn: 0,
+        wireguard_endpoint: String::new(),
+        wireguard_link_local: String::new(),
+        wireguard_public_key: String::new(),
+        mtu: None,
+        persistent_keepalive: None,
+        extra_allowed_ips: Vec::new(),
+        contact: String::new(),
+        notes: String::new(),
+    };
+    for (key, value) in parse_flags(args)? {
+        match key.as_str() {
+            "asn" => peer.asn = parse_number("--asn", &value)?,
+            "endpoint" => peer.wireguard_endpoint = value,
+            "link-local" => peer.wireguard_link_local = value,
+            "public-key" => peer.wireguard_public_key = value,
+            "mtu" => peer.mtu = Some(parse_number("--mtu", &value)?),
+            "keepalive" => peer.persistent_keepalive = Some(parse_number("--keepalive", &value)?),
+            "allowed-ip" => peer.extra_allowed_ips.push(value),
+            "contact" => peer.contact = value,
+            "notes" => peer.notes = value,
+            _ => return Err(format!("Unknown option: --{}", key)),
+        }
+    }
+    if peer.asn == 0 {
+        return Err("Missing --asn".to_string());
+    }
+    Ok(peer)
+}
+
+fn open_db_handle() -> Result<Db, String> {
+    open_database()
+        .map(|conn| Arc::new(Mutex::new(conn)))
+        .map_err(|e| e.to_string())
+}
+
+// 执行结果转换为审计日志所需的状态码与输出
+fn audit_outcome(result: &Result<String, OpError>) -> (StatusCode, String) {
+    match result {
+        Ok(message) => (StatusCode::Ok, message.clone()),
+        Err(e) => (e.status, e.message.clone()),
+    }
+}
+
+fn cmd_add(args: &[String]) -> Result<(), String> {
+    let peer = parse_add_args(args)?;
+    let db = open_db_handle()?;
+    let asn = peer.asn;
+    let payload = serde_json::to_value(&peer).unwrap_or_default();
+    smol::block_on(async {
+        let result = add_peer_flow(peer, db.clone(), false, BirdReload::Immediate)
+            .await
+            .map(|outcome| match outcome {
+                AddOutcome::Pending => format!("Peer pending approval: {}", asn),
+                AddOutcome::Unchanged => format!("Peer already added: {}", asn),
+                _ => format!("Peer added: {}", asn),
+            });
+        let (status, outcome) = audit_outcome(&result);
+        record_cli_action(&db, "add", Some(asn), payload, status, &outcome).await;
+        result
+            .map(|message| println!("{}", message))
+            .map_err(|e| e.message)
+    })
+}
+
+fn cmd_del(args: &[String]) -> Result<(), String> {
+    let asn = parse_asn_arg(args)?;
+    let db = open_db_handle()?;
+    smol::block_on(async {
+        let result = del_peer_flow(asn, db.clone(), BirdReload::Immediate)
+            .await
+            .map(|_| format!("Peer deleted: {}", asn));
+        let (status, outcome) = audit_outcome(&result);
+        let payload = serde_json::json!({ "asn": asn });
+        record_cli_action(&db, "del", Some(asn), payload, status, &outcome).await;
+        result
+            .map(|message| println!("{}", message))
+            .map_err(|e| e.message)
+    })
+}
+
+fn cmd_get(args: &[String]) -> Result<(), String> {
+    let asn = parse_asn_arg(args)?;
+    let conn = open_database().map_err(|e| e.to_string())?;
+    let peer = get_peer_by_asn(&conn, asn).map_err(|e| format!("{}: {}", e, asn))?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&peer).map_err(|e| e.to_string())?
+    );
+    Ok(())
+}
+
+fn cmd_list(args: &[String]) -> Result<(), String> {
+    let mut state = None;
+    for (key, value) in parse_flags(args)? {
+        match key.as_str() {
+            "state" => {
+                state = Some(
+                    PeerState::parse(&value).ok_or_else(|| format!("Unknown state: {}", value))?,
+                )
+            }
+            _ => return Err(format!("Unknown option: --{}", key)),
+        }
+    }
+    let conn = open_database().map_err(|e| e.to_string())?;
+    let peers = list_peers(&conn).map_err(|e| e.to_string())?;
+    println!(
+        "{:<12} {:<12} {:<10} {:<40} LINK-LOCAL",
+        "ASN", "INTERFACE", "STATE", "ENDPOINT"
+    );
+    for peer in peers
+        .iter()
+        .filter(|p| state.is_none_or(|state| p.state == state))
+    {
+        println!(
+            "{:<12} {:<12} {:<10} {:<40} {}",
+            peer.asn,
+            peer.interface_name,
+            peer.state.as_str(),
+            peer.wireguard_endpoint,
+            peer.wireguard_link_local
+        );
+    }
+    Ok(())
+}
+
+fn cmd_reconcile() -> Result<(), String> {
+    let db = open_db_handle()?;
+    smol::block_on(async {
+        let result = resync_peers(db.clone(), true).await;
+        let failed = result
+            .as_ref()
+            .map(|results| results.iter().filter(|r| r.error.is_some()).count())
+            .unwrap_or(0);
+        let (status, outcome) = match &result {
+            Ok(results) => (
+                if failed == 0 {
+                    StatusCode::Ok
+                } else {
+                    StatusCode::InternalServerError
+                },
+                serde_json::to_string(results).unwrap_or_default(),
+            ),
+            Err(e) => (e.status, e.message.clone()),
+        };
+        record_cli_action(&db, "reconcile", None, Value::Null, status, &outcome).await;
+
+        for r in result.map_err(|e| e.message)? {
+            match &r.error {
+                Some(e) => println!("{} {}: failed: {}", r.asn, r.interface_name, e),
+                None if r.changed => println!("{} {}: updated", r.asn, r.interface_name),
+                None => println!("{} {}: unchanged", r.asn, r.interface_name),
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} peers failed to reconcile", failed));
+        }
+        Ok(())
+    })
+}
+
+fn cmd_check_config() -> Result<(), String> {
+    println!("Configuration OK: {}", config_path());
+    Ok(())
+}
+
+fn cmd_render(args: &[String]) -> Result<(), String> {
+    let asn = parse_asn_arg(args)?;
+    let conn = open_database().map_err(|e| e.to_string())?;
+    let info = get_peer_by_asn(&conn, asn).map_err(|e| format!("{}: {}", e, asn))?;
+    let wg_config_path = info.wireguard_config_path.clone();
+    let bird_config_path = info.bird_config_path.clone();
+    let state = info.state;
+    let peer = Peer::from(info);
+    let wg_config = gen_wireguard_config(&peer).map_err(|e| e.to_string())?;
+    let bird_config = gen_bird_config(&peer, state).map_err(|e| e.to_string())?;
+    println!("# {}\n{}\n", wg_config_path, wg_config);
+    println!("# {}\n{}", bird_config_path, bird_config);
+    Ok(())
+}
+
+fn cmd_migrate() -> Result<(), String> {
+    let conn = open_database().map_err(|e| e.to_string())?;
+    println!(
+        "Database schema is at version {}",
+        schema_version(&conn).map_err(|e| e.to_string())?
+    );
+    Ok(())
+}
+
+// 解析子命令之前的全局选项，返回剩余参数
+fn parse_global_options(mut args: &[String]) -> Result<&[String], String> {
+    while let Some((arg, rest)) = args.split_first() {
+        let target = match arg.as_str() {
+            "--config" => &CONFIG_PATH_OVERRIDE,
+            "--db" => &DB_PATH_OVERRIDE,
+            _ => break,
+        };
+        let (value, rest) = rest
+            .split_first()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        target
+            .set(value.clone())
+            .map_err(|_| format!("{} given more than once", arg))?;
+        args = rest;
+    }
+    Ok(args)
+}
+
+pub fn run(args: Vec<String>) -> Result<(), String> {
+    let args = parse_global_options(&args)?;
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => ("serve", &[][..]),
+    };
+
+    // 除 help 外，所有命令都需要有效的配置；只读命令不检查外部程序与输出目录
+    if !matches!(command, "help" | "--help" | "-h") {
+        let check_environment = !matches!(command, "get" | "list" | "render" | "migrate");
+        set_config(load_and_check_config(check_environment)?);
+    }
+
+    match command {
+        "serve" => serve().map_err(|e| e.to_string()),
+        "add" => cmd_add(rest),
+        "del" => cmd_del(rest),
+        "get" => cmd_get(rest),
+        "list" => cmd_list(rest),
+        "reconcile" => cmd_reconcile(),
+        "check-config" => cmd_check_config(),
+        "render" => cmd_render(rest),
+        "migrate" => cmd_migrate(),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("Unknown command: {}\n\n{}", command, USAGE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_add_args() {
+        let peer = parse_add_args(&args(&[
+            "--asn",
+            "4242420253",
+            "--endpoint",
+            "1.2.3.4:51820",
+            "--link-local",
+            "fe80::1",
+            "--public-key",
+            "key",
+            "--mtu",
+            "1400",
+            "--allowed-ip",
+            "10.127.0.0/16",
+            "--allowed-ip",
+            "fd10::/8",
+        ]))
+        .unwrap();
+        assert_eq!(peer.asn, 4242420253);
+        assert_eq!(peer.wireguard_endpoint, "1.2.3.4:51820");
+        assert_eq!(peer.mtu, Some(1400));
+        assert_eq!(peer.extra_allowed_ips, vec!["10.127.0.0/16", "fd10::/8"]);
+    }
+
+    #[test]
+    fn test_parse_add_args_errors() {
+        assert!(parse_add_args(&args(&["--endpoint", "1.2.3.4:1"])).is_err());
+        assert!(parse_add_args(&args(&["--asn"])).is_err());
+        assert!(parse_add_args(&args(&["--asn", "abc"])).is_err());
+        assert!(parse_add_args(&args(&["--asn", "1", "--bogus", "x"])).is_err());
+        assert!(parse_add_args(&args(&["4242420253"])).is_err());
+    }
+}
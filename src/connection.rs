@@ -0,0 +1,331 @@
+use async_io::Timer;
+use futures_lite::io::{self, AsyncRead, AsyncReadExt, AsyncWrite};
+use futures_lite::{Future, FutureExt};
+use http_types::{Body, Request, Response, StatusCode};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    // 等待下一个请求（包括第一个）的最长时间，超时后静默关闭连接
+    pub idle_timeout: Duration,
+    // 从收到第一个字节到读完请求头的最长时间
+    pub header_timeout: Duration,
+    // 读取请求体时两次收到数据之间的最长间隔
+    pub read_timeout: Duration,
+    pub max_body_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Headers,
+    Body,
+    // 已决定关闭连接，之后的读取直接返回 EOF，避免继续接收被拒绝的请求体
+    Closing,
+}
+
+#[derive(Debug)]
+struct PhaseState {
+    phase: Phase,
+    since: Instant,
+}
+
+// 按连接所处阶段为读取设置超时
+struct TimedStream<S> {
+    inner: S,
+    limits: Limits,
+    state: Arc<Mutex<PhaseState>>,
+    timer: Option<(Phase, Timer)>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TimedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let mut state = this.state.lock().unwrap();
+        if state.phase == Phase::Closing {
+            return Poll::Ready(Ok(0));
+        }
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                this.timer = None;
+                if n > 0 && state.phase == Phase::Idle {
+                    state.phase = Phase::Headers;
+                    state.since = Instant::now();
+                }
+                return Poll::Ready(Ok(n));
+            }
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => {}
+        }
+
+        let phase = state.phase;
+        if !matches!(&this.timer, Some((armed, _)) if *armed == phase) {
+            let deadline = match phase {
+                Phase::Idle => state.since + this.limits.idle_timeout,
+                Phase::Headers => state.since + this.limits.header_timeout,
+                _ => Instant::now() + this.limits.read_timeout,
+            };
+            this.timer = Some((phase, Timer::at(deadline)));
+        }
+        let Some((_, timer)) = &mut this.timer else {
+            return Poll::Pending;
+        };
+        if timer.poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        this.timer = None;
+        match phase {
+            Phase::Idle => Poll::Ready(Ok(0)),
+            Phase::Headers => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out reading request headers",
+            ))),
+            _ => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out reading request body",
+            ))),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TimedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+// async_h1 要求连接可克隆，TCP、TLS 与 Unix socket 连接都通过它共享
+struct CloneableStream<S> {
+    inner: Arc<Mutex<S>>,
+}
+
+impl<S> Clone for CloneableStream<S> {
+    fn clone(&self) -> Self {
+        CloneableStream {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CloneableStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = self.inner.lock().unwrap();
+        Pin::new(&mut *inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CloneableStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = self.inner.lock().unwrap();
+        Pin::new(&mut *inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut inner = self.inner.lock().unwrap();
+        Pin::new(&mut *inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut inner = self.inner.lock().unwrap();
+        Pin::new(&mut *inner).poll_close(cx)
+    }
+}
+
+fn payload_too_large() -> Response {
+    let mut res = Response::new(StatusCode::PayloadTooLarge);
+    res.insert_header("Connection", "close");
+    res.insert_header("Content-Type", "text/plain; charset=utf-8");
+    res.set_body("Payload Too Large\n".to_string());
+    res
+}
+
+// 将请求体完整读入内存，超过 max_body_size 时返回 None
+async fn read_body(req: &mut Request, max_body_size: usize) -> io::Result<Option<Body>> {
+    if req.len().is_some_and(|len| len > max_body_size) {
+        return Ok(None);
+    }
+    let mut buf = Vec::new();
+    req.take_body()
+        .take(max_body_size as u64 + 1)
+        .read_to_end(&mut buf)
+        .await?;
+    if buf.len() > max_body_size {
+        return Ok(None);
+    }
+    let mut body = Body::from_bytes(buf);
+    if let Some(mime) = req.content_type() {
+        body.set_mime(mime);
+    }
+    Ok(Some(body))
+}
+
+fn wants_close(connection: Option<&http_types::headers::HeaderValues>) -> bool {
+    connection.is_some_and(|value| value.as_str().eq_ignore_ascii_case("close"))
+}
+
+// 替代 async_h1::server::accept 的请求循环，在其基础上加入超时与请求体大小限制
+pub async fn serve<S, F, Fut>(stream: S, limits: Limits, endpoint: F) -> http_types::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = http_types::Result<Response>>,
+{
+    let state = Arc::new(Mutex::new(PhaseState {
+        phase: Phase::Idle,
+        since: Instant::now(),
+    }));
+    let set_phase = |phase| {
+        let mut state = state.lock().unwrap();
+        state.phase = phase;
+        state.since = Instant::now();
+    };
+    let mut io = CloneableStream {
+        inner: Arc::new(Mutex::new(TimedStream {
+            inner: stream,
+            limits,
+            state: state.clone(),
+            timer: None,
+        })),
+    };
+
+    loop {
+        set_phase(Phase::Idle);
+        let Some((mut req, mut body)) = async_h1::server::decode(io.clone()).await? else {
+            return Ok(());
+        };
+        set_phase(Phase::Body);
+        let method = req.method();
+        let mut close = wants_close(req.header("Connection"));
+
+        let res = match read_body(&mut req, limits.max_body_size).await? {
+            Some(buffered) => {
+                req.set_body(buffered);
+                endpoint(req).await?
+            }
+            None => {
+                set_phase(Phase::Closing);
+                payload_too_large()
+            }
+        };
+        close |= wants_close(res.header("Connection"));
+
+        let mut encoder = async_h1::server::Encoder::new(res, method);
+        io::copy(&mut encoder, &mut io).await?;
+        // 丢弃未读完的请求体，Closing 状态下立即结束
+        io::copy(&mut body, &mut io::sink()).await?;
+        if close || state.lock().unwrap().phase == Phase::Closing {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use futures_lite::AsyncWriteExt;
+    use std::os::unix::net::UnixStream;
+
+    fn test_limits() -> Limits {
+        Limits {
+            idle_timeout: Duration::from_millis(200),
+            header_timeout: Duration::from_millis(200),
+            read_timeout: Duration::from_millis(200),
+            max_body_size: 16,
+        }
+    }
+
+    // 返回请求体长度，便于确认请求体已完整读入
+    async fn echo_len(mut req: Request) -> http_types::Result<Response> {
+        let body = req.body_string().await?;
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body(body.len().to_string());
+        Ok(res)
+    }
+
+    async fn exchange(request: &[u8]) -> String {
+        let (server, mut client) = Async::<UnixStream>::pair().unwrap();
+        let task = smol::spawn(serve(server, test_limits(), echo_len));
+        client.write_all(request).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        task.await.ok();
+        response
+    }
+
+    #[test]
+    fn test_body_limit() {
+        smol::block_on(async {
+            let response = exchange(
+                b"POST /add HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+            )
+            .await;
+            // keep-alive 的连接在空闲超时后关闭
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.ends_with("\r\n\r\n5"));
+
+            let response = exchange(
+                b"POST /add HTTP/1.1\r\nHost: localhost\r\nContent-Length: 17\r\n\r\n01234567890123456",
+            )
+            .await;
+            assert!(response.starts_with("HTTP/1.1 413"));
+
+            let response = exchange(
+                b"POST /add HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n11\r\n01234567890123456\r\n0\r\n\r\n",
+            )
+            .await;
+            assert!(response.starts_with("HTTP/1.1 413"));
+        });
+    }
+
+    #[test]
+    fn test_timeouts() {
+        smol::block_on(async {
+            // 请求头不完整
+            let (server, mut client) = Async::<UnixStream>::pair().unwrap();
+            let task = smol::spawn(serve(server, test_limits(), echo_len));
+            client.write_all(b"POST /add HTTP/1.1\r\n").await.unwrap();
+            assert!(task.await.is_err());
+
+            // 请求体未发完
+            let (server, mut client) = Async::<UnixStream>::pair().unwrap();
+            let task = smol::spawn(serve(server, test_limits(), echo_len));
+            client
+                .write_all(b"POST /add HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhel")
+                .await
+                .unwrap();
+            assert!(task.await.is_err());
+
+            // 空闲连接正常关闭
+            let (server, _client) = Async::<UnixStream>::pair().unwrap();
+            assert!(serve(server, test_limits(), echo_len).await.is_ok());
+        });
+    }
+}
@@ -1,5 +1,6 @@
 use crate::model::*;
 use anyhow::Result;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
 use rusqlite::*;
 
 #[derive(Debug)]
@@ -42,26 +43,234 @@ impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, rusqlite::Connection>
     }
 }
 
-pub fn init_db(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS peers (
-            asn                 INTEGER PRIMARY KEY,
-            wireguard_endpoint  TEXT NOT NULL,
-            wireguard_link_local TEXT NOT NULL,
-            wireguard_public_key TEXT NOT NULL,
-            interface_name TEXT NOT NULL,
-            wireguard_config_path TEXT NOT NULL,
-            bird_config_path TEXT NOT NULL
-        )",
+fn split_allowed_ips(joined: &str) -> Vec<String> {
+    joined
+        .split(',')
+        .map(str::trim)
+        .filter(|cidr| !cidr.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl ToSql for PeerState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for PeerState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        PeerState::parse(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown peer state: {}", s).into()))
+    }
+}
+
+impl ToSql for JobAction {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for JobAction {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        JobAction::parse(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown job action: {}", s).into()))
+    }
+}
+
+impl ToSql for JobStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for JobStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        JobStatus::parse(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown job status: {}", s).into()))
+    }
+}
+
+// 数据库迁移步骤，按顺序执行，第 N 个步骤执行后 user_version 为 N
+// 已发布的步骤不可修改，只能在末尾追加
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE IF NOT EXISTS peers (
+        asn                 INTEGER PRIMARY KEY,
+        wireguard_endpoint  TEXT NOT NULL,
+        wireguard_link_local TEXT NOT NULL,
+        wireguard_public_key TEXT NOT NULL,
+        interface_name TEXT NOT NULL,
+        wireguard_config_path TEXT NOT NULL,
+        bird_config_path TEXT NOT NULL
+    );",
+    // 2: per-peer WireGuard overrides
+    "ALTER TABLE peers ADD COLUMN mtu INTEGER;
+     ALTER TABLE peers ADD COLUMN persistent_keepalive INTEGER;
+     ALTER TABLE peers ADD COLUMN extra_allowed_ips TEXT NOT NULL DEFAULT '';",
+    // 3: audit log
+    "CREATE TABLE audit_log (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp   INTEGER NOT NULL,
+        action      TEXT NOT NULL,
+        asn         INTEGER,
+        principal   TEXT NOT NULL,
+        source_ip   TEXT,
+        payload     TEXT NOT NULL,
+        status      INTEGER NOT NULL,
+        outcome     TEXT NOT NULL
+    );
+    CREATE INDEX audit_log_asn_timestamp ON audit_log (asn, timestamp);
+    CREATE INDEX audit_log_timestamp ON audit_log (timestamp);",
+    // 4: timestamps and operator metadata
+    "ALTER TABLE peers ADD COLUMN created_at INTEGER;
+     ALTER TABLE peers ADD COLUMN updated_at INTEGER;
+     ALTER TABLE peers ADD COLUMN contact TEXT NOT NULL DEFAULT '';
+     ALTER TABLE peers ADD COLUMN notes TEXT NOT NULL DEFAULT '';",
+    // 5: soft-disable
+    "ALTER TABLE peers ADD COLUMN state TEXT NOT NULL DEFAULT 'active';",
+    // 6: approval workflow
+    "ALTER TABLE peers ADD COLUMN reject_reason TEXT NOT NULL DEFAULT '';",
+    // 7: asynchronous job queue
+    "CREATE TABLE jobs (
+        id              INTEGER PRIMARY KEY AUTOINCREMENT,
+        action          TEXT NOT NULL,
+        asn             INTEGER,
+        payload         TEXT NOT NULL,
+        principal       TEXT NOT NULL,
+        source_ip       TEXT,
+        status          TEXT NOT NULL,
+        result_status   INTEGER,
+        result          TEXT NOT NULL DEFAULT '',
+        created_at      INTEGER NOT NULL,
+        started_at      INTEGER,
+        finished_at     INTEGER
+    );
+    CREATE INDEX jobs_status_id ON jobs (status, id);
+    CREATE TABLE job_log (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        job_id      INTEGER NOT NULL REFERENCES jobs (id),
+        timestamp   INTEGER NOT NULL,
+        message     TEXT NOT NULL
+    );
+    CREATE INDEX job_log_job_id ON job_log (job_id, id);",
+    // 8: idempotency keys
+    "CREATE TABLE idempotency_keys (
+        principal       TEXT NOT NULL,
+        key             TEXT NOT NULL,
+        fingerprint     TEXT NOT NULL,
+        created_at      INTEGER NOT NULL,
+        status          INTEGER,
+        content_type    TEXT,
+        location        TEXT,
+        body            TEXT,
+        PRIMARY KEY (principal, key)
+    );
+    CREATE INDEX idempotency_keys_created_at ON idempotency_keys (created_at);",
+];
+
+pub fn open_db(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    // CLI 与守护进程可能同时访问数据库
+    conn.busy_timeout(std::time::Duration::from_secs(5))?;
+    Ok(conn)
+}
+
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+pub fn latest_schema_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+fn has_peers_table(conn: &Connection) -> Result<bool> {
+    let count: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'peers'",
         (),
+        |row| row.get(0),
     )?;
+    Ok(count > 0)
+}
+
+// 迁移前将数据库复制一份，返回备份文件路径；内存数据库或全新数据库不备份
+fn backup_db(conn: &Connection, version: u32) -> Result<Option<String>> {
+    let Some(path) = conn.path().filter(|p| !p.is_empty()) else {
+        return Ok(None);
+    };
+    if version == 0 && !has_peers_table(conn)? {
+        return Ok(None);
+    }
+    let backup_path = format!("{}.v{}.bak", path, version);
+    if std::path::Path::new(&backup_path).exists() {
+        std::fs::remove_file(&backup_path)?;
+    }
+    conn.execute("VACUUM INTO ?1", rusqlite::params![backup_path])?;
+    Ok(Some(backup_path))
+}
+
+pub fn init_db(conn: &Connection) -> Result<()> {
+    let current = schema_version(conn)?;
+    let latest = latest_schema_version();
+    if current > latest {
+        return Err(anyhow::anyhow!(
+            "Database schema version {} is newer than the supported version {}, refusing to start",
+            current,
+            latest
+        ));
+    }
+    if current == latest {
+        return Ok(());
+    }
+
+    if let Some(backup_path) = backup_db(conn, current)? {
+        println!("Database backup written to {}", backup_path);
+    }
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = index as u32 + 1;
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(sql).map_err(|e| {
+            anyhow::anyhow!("Database migration to version {} failed: {}", version, e)
+        })?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+    println!("Database migrated from version {} to {}", current, latest);
     Ok(())
 }
 
-pub fn add_peer(conn: &Connection, peer: &Peer) -> Result<usize, PeerDbError> {
+// 已被拒绝的 ASN 可以重新提交，覆盖原有记录
+pub fn add_peer(conn: &Connection, peer: &Peer, state: PeerState) -> Result<usize, PeerDbError> {
     let result = conn.execute(
-        "INSERT INTO peers (asn, wireguard_endpoint, wireguard_link_local, wireguard_public_key, interface_name, wireguard_config_path, bird_config_path)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        "INSERT INTO peers (asn, wireguard_endpoint, wireguard_link_local, wireguard_public_key, interface_name, wireguard_config_path, bird_config_path, mtu, persistent_keepalive, extra_allowed_ips, contact, notes, created_at, updated_at, state, reject_reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13, ?14, '')
+         ON CONFLICT (asn) DO UPDATE SET
+             wireguard_endpoint = excluded.wireguard_endpoint,
+             wireguard_link_local = excluded.wireguard_link_local,
+             wireguard_public_key = excluded.wireguard_public_key,
+             interface_name = excluded.interface_name,
+             wireguard_config_path = excluded.wireguard_config_path,
+             bird_config_path = excluded.bird_config_path,
+             mtu = excluded.mtu,
+             persistent_keepalive = excluded.persistent_keepalive,
+             extra_allowed_ips = excluded.extra_allowed_ips,
+             contact = excluded.contact,
+             notes = excluded.notes,
+             created_at = excluded.created_at,
+             updated_at = excluded.updated_at,
+             state = excluded.state,
+             reject_reason = ''
+         WHERE peers.state = 'rejected'",
         rusqlite::params![
             peer.asn,
             peer.wireguard_endpoint,
@@ -69,28 +278,110 @@ pub fn add_peer(conn: &Connection, peer: &Peer) -> Result<usize, PeerDbError> {
             peer.wireguard_public_key,
             peer.gen_interface_name(),
             peer.gen_wireguard_config_path(),
-            peer.gen_bird_config_path()
+            peer.gen_bird_config_path(),
+            peer.mtu,
+            peer.persistent_keepalive,
+            peer.extra_allowed_ips.join(","),
+            peer.contact,
+            peer.notes,
+            unix_now(),
+            state
+        ],
+    );
+
+    match result {
+        Ok(0) => Err(PeerDbError::AlreadyExist),
+        Ok(rows) => Ok(rows),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// 接管手工创建的 peer，配置文件路径以实际文件为准
+pub fn adopt_peer(
+    conn: &Connection,
+    peer: &Peer,
+    state: PeerState,
+    wg_config_path: &str,
+    bird_config_path: &str,
+) -> Result<usize, PeerDbError> {
+    let result = conn.execute(
+        "INSERT INTO peers (asn, wireguard_endpoint, wireguard_link_local, wireguard_public_key, interface_name, wireguard_config_path, bird_config_path, mtu, persistent_keepalive, extra_allowed_ips, contact, notes, created_at, updated_at, state)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13, ?14)",
+        rusqlite::params![
+            peer.asn,
+            peer.wireguard_endpoint,
+            peer.wireguard_link_local,
+            peer.wireguard_public_key,
+            peer.gen_interface_name(),
+            wg_config_path,
+            bird_config_path,
+            peer.mtu,
+            peer.persistent_keepalive,
+            peer.extra_allowed_ips.join(","),
+            peer.contact,
+            peer.notes,
+            unix_now(),
+            state
         ],
     );
 
     result.map_err(Into::into)
 }
 
+// 更新 peer 的可修改字段，接口名与配置文件路径由 ASN 决定，不会改变
+pub fn update_peer(conn: &Connection, peer: &Peer) -> Result<usize, PeerDbError> {
+    let rows_affected = conn.execute(
+        "UPDATE peers SET wireguard_endpoint = ?2, wireguard_link_local = ?3, wireguard_public_key = ?4,
+             mtu = ?5, persistent_keepalive = ?6, extra_allowed_ips = ?7, contact = ?8, notes = ?9, updated_at = ?10
+         WHERE asn = ?1",
+        rusqlite::params![
+            peer.asn,
+            peer.wireguard_endpoint,
+            peer.wireguard_link_local,
+            peer.wireguard_public_key,
+            peer.mtu,
+            peer.persistent_keepalive,
+            peer.extra_allowed_ips.join(","),
+            peer.contact,
+            peer.notes,
+            unix_now()
+        ],
+    )?;
+
+    if rows_affected == 0 {
+        return Err(PeerDbError::NotFound);
+    }
+    Ok(rows_affected)
+}
+
+const PEER_COLUMNS: &str = "asn, wireguard_endpoint, wireguard_link_local, wireguard_public_key, interface_name, wireguard_config_path, bird_config_path, mtu, persistent_keepalive, extra_allowed_ips, contact, notes, created_at, updated_at, state, reject_reason";
+
+fn peer_from_row(row: &Row) -> rusqlite::Result<PeerDbInfo> {
+    Ok(PeerDbInfo {
+        asn: row.get(0)?,
+        wireguard_endpoint: row.get(1)?,
+        wireguard_link_local: row.get(2)?,
+        wireguard_public_key: row.get(3)?,
+        interface_name: row.get(4)?,
+        wireguard_config_path: row.get(5)?,
+        bird_config_path: row.get(6)?,
+        mtu: row.get(7)?,
+        persistent_keepalive: row.get(8)?,
+        extra_allowed_ips: split_allowed_ips(&row.get::<_, String>(9)?),
+        contact: row.get(10)?,
+        notes: row.get(11)?,
+        created_at: row.get(12)?,
+        updated_at: row.get(13)?,
+        state: row.get(14)?,
+        reject_reason: row.get(15)?,
+    })
+}
+
 pub fn get_peer_by_asn(conn: &Connection, asn: u64) -> Result<PeerDbInfo, PeerDbError> {
     let peer = conn.query_row(
-        "SELECT asn, wireguard_endpoint, wireguard_link_local, wireguard_public_key, interface_name, wireguard_config_path, bird_config_path FROM peers WHERE asn = ?1",
+        &format!("SELECT {} FROM peers WHERE asn = ?1", PEER_COLUMNS),
         rusqlite::params![asn],
-        |row| {
-            Ok(PeerDbInfo {
-                asn: row.get(0)?,
-                wireguard_endpoint: row.get(1)?,
-                wireguard_link_local: row.get(2)?,
-                wireguard_public_key: row.get(3)?,
-                interface_name: row.get(4)?,
-                wireguard_config_path: row.get(5)?,
-                bird_config_path: row.get(6)?
-            })
-        },
+        peer_from_row,
     );
 
     match peer {
@@ -99,6 +390,38 @@ pub fn get_peer_by_asn(conn: &Connection, asn: u64) -> Result<PeerDbInfo, PeerDb
     }
 }
 
+pub fn list_peers(conn: &Connection) -> Result<Vec<PeerDbInfo>, PeerDbError> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM peers ORDER BY asn", PEER_COLUMNS))?;
+    let peers = stmt
+        .query_map([], peer_from_row)?
+        .collect::<rusqlite::Result<Vec<PeerDbInfo>>>()?;
+    Ok(peers)
+}
+
+pub fn set_peer_state(conn: &Connection, asn: u64, state: PeerState) -> Result<usize, PeerDbError> {
+    let rows_affected = conn.execute(
+        "UPDATE peers SET state = ?2, updated_at = ?3 WHERE asn = ?1",
+        rusqlite::params![asn, state, unix_now()],
+    )?;
+
+    if rows_affected == 0 {
+        return Err(PeerDbError::NotFound);
+    }
+    Ok(rows_affected)
+}
+
+pub fn reject_peer(conn: &Connection, asn: u64, reason: &str) -> Result<usize, PeerDbError> {
+    let rows_affected = conn.execute(
+        "UPDATE peers SET state = ?2, reject_reason = ?3, updated_at = ?4 WHERE asn = ?1",
+        rusqlite::params![asn, PeerState::Rejected, reason, unix_now()],
+    )?;
+
+    if rows_affected == 0 {
+        return Err(PeerDbError::NotFound);
+    }
+    Ok(rows_affected)
+}
+
 // usize 为受影响行数
 pub fn delete_peer_by_asn(conn: &Connection, asn: u64) -> Result<usize, PeerDbError> {
     let rows_affected = conn.execute("DELETE FROM peers WHERE asn = ?1", rusqlite::params![asn])?;
@@ -108,3 +431,540 @@ pub fn delete_peer_by_asn(conn: &Connection, asn: u64) -> Result<usize, PeerDbEr
     }
     Ok(rows_affected)
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn insert_audit(
+    conn: &Connection,
+    action: &str,
+    asn: Option<u64>,
+    principal: &str,
+    source_ip: Option<&str>,
+    payload: &serde_json::Value,
+    status: u16,
+    outcome: &str,
+) -> Result<usize, PeerDbError> {
+    let rows = conn.execute(
+        "INSERT INTO audit_log (timestamp, action, asn, principal, source_ip, payload, status, outcome)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            unix_now(),
+            action,
+            asn,
+            principal,
+            source_ip,
+            payload.to_string(),
+            status,
+            outcome
+        ],
+    )?;
+    Ok(rows)
+}
+
+// 按时间倒序返回
+pub fn query_audit(conn: &Connection, query: &AuditQuery) -> Result<Vec<AuditEntry>, PeerDbError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, timestamp, action, asn, principal, source_ip, payload, status, outcome FROM audit_log
+         WHERE (?1 IS NULL OR asn = ?1)
+           AND (?2 IS NULL OR timestamp >= ?2)
+           AND (?3 IS NULL OR timestamp <= ?3)
+         ORDER BY timestamp DESC, id DESC
+         LIMIT ?4",
+    )?;
+    let entries = stmt
+        .query_map(
+            rusqlite::params![query.asn, query.since, query.until, query.limit],
+            |row| {
+                let payload: String = row.get(6)?;
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    action: row.get(2)?,
+                    asn: row.get(3)?,
+                    principal: row.get(4)?,
+                    source_ip: row.get(5)?,
+                    payload: serde_json::from_str(&payload)
+                        .unwrap_or(serde_json::Value::String(payload)),
+                    status: row.get(7)?,
+                    outcome: row.get(8)?,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<AuditEntry>>>()?;
+    Ok(entries)
+}
+
+pub fn append_job_log(conn: &Connection, job_id: i64, message: &str) -> Result<(), PeerDbError> {
+    conn.execute(
+        "INSERT INTO job_log (job_id, timestamp, message) VALUES (?1, ?2, ?3)",
+        rusqlite::params![job_id, unix_now(), message],
+    )?;
+    Ok(())
+}
+
+pub fn insert_job(
+    conn: &Connection,
+    action: JobAction,
+    asn: Option<u64>,
+    payload: &str,
+    principal: &str,
+    source_ip: Option<&str>,
+) -> Result<i64, PeerDbError> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO jobs (action, asn, payload, principal, source_ip, status, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            action,
+            asn,
+            payload,
+            principal,
+            source_ip,
+            JobStatus::Queued,
+            unix_now()
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+    append_job_log(&tx, id, "Queued")?;
+    tx.commit()?;
+    Ok(id)
+}
+
+const JOB_COLUMNS: &str = "id, action, asn, status, result_status, result, principal, payload, source_ip, created_at, started_at, finished_at";
+
+fn job_from_row(row: &Row) -> rusqlite::Result<Job> {
+    Ok(Job {
+        id: row.get(0)?,
+        action: row.get(1)?,
+        asn: row.get(2)?,
+        status: row.get(3)?,
+        result_status: row.get(4)?,
+        result: row.get(5)?,
+        principal: row.get(6)?,
+        payload: row.get(7)?,
+        source_ip: row.get(8)?,
+        created_at: row.get(9)?,
+        started_at: row.get(10)?,
+        finished_at: row.get(11)?,
+        log: Vec::new(),
+    })
+}
+
+pub fn get_job(conn: &Connection, id: i64) -> Result<Job, PeerDbError> {
+    let mut job = conn.query_row(
+        &format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS),
+        rusqlite::params![id],
+        job_from_row,
+    )?;
+    let mut stmt =
+        conn.prepare("SELECT timestamp, message FROM job_log WHERE job_id = ?1 ORDER BY id")?;
+    job.log = stmt
+        .query_map(rusqlite::params![id], |row| {
+            Ok(JobLogEntry {
+                timestamp: row.get(0)?,
+                message: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<JobLogEntry>>>()?;
+    Ok(job)
+}
+
+// 取出最早的排队任务并标记为运行中，没有任务时返回 None
+pub fn claim_next_job(conn: &Connection) -> Result<Option<Job>, PeerDbError> {
+    let tx = conn.unchecked_transaction()?;
+    let job = match tx.query_row(
+        &format!(
+            "SELECT {} FROM jobs WHERE status = ?1 ORDER BY id LIMIT 1",
+            JOB_COLUMNS
+        ),
+        rusqlite::params![JobStatus::Queued],
+        job_from_row,
+    ) {
+        Ok(job) => job,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    tx.execute(
+        "UPDATE jobs SET status = ?1, started_at = ?2 WHERE id = ?3",
+        rusqlite::params![JobStatus::Running, unix_now(), job.id],
+    )?;
+    append_job_log(&tx, job.id, "Started")?;
+    tx.commit()?;
+    Ok(Some(job))
+}
+
+pub fn finish_job(
+    conn: &Connection,
+    id: i64,
+    status: JobStatus,
+    result_status: u16,
+    result: &str,
+) -> Result<(), PeerDbError> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE jobs SET status = ?1, result_status = ?2, result = ?3, finished_at = ?4 WHERE id = ?5",
+        rusqlite::params![status, result_status, result, unix_now(), id],
+    )?;
+    append_job_log(&tx, id, &format!("{}: {}", status.as_str(), result))?;
+    tx.commit()?;
+    Ok(())
+}
+
+// 上次运行时被中断的任务重新排队，返回排队中的任务总数
+pub fn requeue_interrupted_jobs(conn: &Connection) -> Result<usize, PeerDbError> {
+    let tx = conn.unchecked_transaction()?;
+    let interrupted: Vec<i64> = tx
+        .prepare("SELECT id FROM jobs WHERE status = ?1")?
+        .query_map(rusqlite::params![JobStatus::Running], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    for id in interrupted {
+        tx.execute(
+            "UPDATE jobs SET status = ?1, started_at = NULL WHERE id = ?2",
+            rusqlite::params![JobStatus::Queued, id],
+        )?;
+        append_job_log(&tx, id, "Requeued after restart")?;
+    }
+    let queued: usize = tx.query_row(
+        "SELECT COUNT(*) FROM jobs WHERE status = ?1",
+        rusqlite::params![JobStatus::Queued],
+        |row| row.get(0),
+    )?;
+    tx.commit()?;
+    Ok(queued)
+}
+
+// 登记一个 Idempotency-Key，同时清理超过 ttl 秒的记录
+pub fn claim_idempotency_key(
+    conn: &Connection,
+    principal: &str,
+    key: &str,
+    fingerprint: &str,
+    ttl: i64,
+) -> Result<IdempotencyClaim, PeerDbError> {
+    let now = unix_now();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM idempotency_keys WHERE created_at < ?1",
+        rusqlite::params![now - ttl],
+    )?;
+    let inserted = tx.execute(
+        "INSERT OR IGNORE INTO idempotency_keys (principal, key, fingerprint, created_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![principal, key, fingerprint, now],
+    )?;
+    let claim = if inserted > 0 {
+        IdempotencyClaim::Claimed
+    } else {
+        let (stored_fingerprint, status, content_type, location, body): (
+            String,
+            Option<u16>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ) = tx.query_row(
+            "SELECT fingerprint, status, content_type, location, body FROM idempotency_keys
+             WHERE principal = ?1 AND key = ?2",
+            rusqlite::params![principal, key],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )?;
+        match status {
+            _ if stored_fingerprint != fingerprint => IdempotencyClaim::Mismatch,
+            None => IdempotencyClaim::InProgress,
+            Some(status) => IdempotencyClaim::Completed(StoredResponse {
+                status,
+                content_type,
+                location,
+                body: body.unwrap_or_default(),
+            }),
+        }
+    };
+    tx.commit()?;
+    Ok(claim)
+}
+
+pub fn complete_idempotency_key(
+    conn: &Connection,
+    principal: &str,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<(), PeerDbError> {
+    conn.execute(
+        "UPDATE idempotency_keys SET status = ?1, content_type = ?2, location = ?3, body = ?4
+         WHERE principal = ?5 AND key = ?6",
+        rusqlite::params![
+            response.status,
+            response.content_type,
+            response.location,
+            response.body,
+            principal,
+            key
+        ],
+    )?;
+    Ok(())
+}
+
+// 请求未完成（服务端错误）时释放 key，允许客户端重试
+pub fn release_idempotency_key(
+    conn: &Connection,
+    principal: &str,
+    key: &str,
+) -> Result<(), PeerDbError> {
+    conn.execute(
+        "DELETE FROM idempotency_keys WHERE principal = ?1 AND key = ?2",
+        rusqlite::params![principal, key],
+    )?;
+    Ok(())
+}
+
+// 上次运行时未完成的请求没有保存结果，启动时释放对应的 key
+pub fn release_unfinished_idempotency_keys(conn: &Connection) -> Result<usize, PeerDbError> {
+    Ok(conn.execute("DELETE FROM idempotency_keys WHERE status IS NULL", ())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute(
+            "INSERT INTO peers VALUES (4242420253, '1.2.3.4:51820', 'fe80::1', 'key', 'dn42_0253', '/etc/wireguard/dn42_0253.conf', '/etc/bird/peers/dn42_0253.conf')",
+            (),
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_migrate_fresh_db() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_schema_version());
+        assert!(list_peers(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_legacy_db_keeps_rows() {
+        let conn = legacy_db();
+        init_db(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_schema_version());
+        let peer = get_peer_by_asn(&conn, 4242420253).unwrap();
+        assert_eq!(peer.interface_name, "dn42_0253");
+        assert_eq!(peer.mtu, None);
+        assert!(peer.extra_allowed_ips.is_empty());
+        assert_eq!(peer.created_at, None);
+        assert_eq!(peer.state, PeerState::Active);
+        // running again is a no-op
+        init_db(&conn).unwrap();
+    }
+
+    #[test]
+    fn test_update_peer_bumps_updated_at() {
+        let conn = legacy_db();
+        init_db(&conn).unwrap();
+        let mut peer = Peer::from(get_peer_by_asn(&conn, 4242420253).unwrap());
+        peer.notes = "maintenance window on sundays".to_string();
+        update_peer(&conn, &peer).unwrap();
+        let stored = get_peer_by_asn(&conn, 4242420253).unwrap();
+        assert_eq!(stored.notes, peer.notes);
+        assert!(stored.updated_at.is_some());
+        set_peer_state(&conn, 4242420253, PeerState::Disabled).unwrap();
+        assert_eq!(
+            get_peer_by_asn(&conn, 4242420253).unwrap().state,
+            PeerState::Disabled
+        );
+        peer.asn = 4242421234;
+        assert!(matches!(
+            update_peer(&conn, &peer),
+            Err(PeerDbError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_add_peer_replaces_only_rejected() {
+        let conn = legacy_db();
+        init_db(&conn).unwrap();
+        let mut peer = Peer::from(get_peer_by_asn(&conn, 4242420253).unwrap());
+        assert!(matches!(
+            add_peer(&conn, &peer, PeerState::Pending),
+            Err(PeerDbError::AlreadyExist)
+        ));
+        reject_peer(&conn, peer.asn, "not in registry").unwrap();
+        assert_eq!(
+            get_peer_by_asn(&conn, peer.asn).unwrap().reject_reason,
+            "not in registry"
+        );
+        peer.wireguard_endpoint = "5.6.7.8:51820".to_string();
+        add_peer(&conn, &peer, PeerState::Pending).unwrap();
+        let stored = get_peer_by_asn(&conn, peer.asn).unwrap();
+        assert_eq!(stored.state, PeerState::Pending);
+        assert_eq!(stored.reject_reason, "");
+        assert_eq!(stored.wireguard_endpoint, "5.6.7.8:51820");
+    }
+
+    #[test]
+    fn test_audit_query_filters() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        let payload = serde_json::json!({"asn": 4242420253u64});
+        insert_audit(
+            &conn,
+            "add",
+            Some(4242420253),
+            "token",
+            Some("127.0.0.1"),
+            &payload,
+            200,
+            "ok",
+        )
+        .unwrap();
+        insert_audit(
+            &conn,
+            "del",
+            Some(4242421234),
+            "token",
+            None,
+            &payload,
+            400,
+            "err",
+        )
+        .unwrap();
+        insert_audit(
+            &conn,
+            "resync",
+            None,
+            "token",
+            None,
+            &serde_json::Value::Null,
+            200,
+            "ok",
+        )
+        .unwrap();
+
+        let mut query = AuditQuery {
+            asn: Some(4242420253),
+            since: None,
+            until: None,
+            limit: DEFAULT_AUDIT_LIMIT,
+        };
+        let entries = query_audit(&conn, &query).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "add");
+        assert_eq!(entries[0].payload, payload);
+
+        query.asn = None;
+        assert_eq!(query_audit(&conn, &query).unwrap().len(), 3);
+        query.until = Some(0);
+        assert!(query_audit(&conn, &query).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_refuse_newer_db() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_schema_version() + 1)
+            .unwrap();
+        assert!(init_db(&conn).is_err());
+    }
+
+    #[test]
+    fn test_job_queue() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        let first =
+            insert_job(&conn, JobAction::Add, Some(4242420253), "{}", "token", None).unwrap();
+        let second =
+            insert_job(&conn, JobAction::Del, Some(4242420254), "{}", "token", None).unwrap();
+
+        let job = claim_next_job(&conn).unwrap().unwrap();
+        assert_eq!(job.id, first);
+        assert_eq!(job.status, JobStatus::Queued);
+        assert_eq!(get_job(&conn, first).unwrap().status, JobStatus::Running);
+
+        // 模拟重启：运行中的任务重新排队并被优先取出
+        assert_eq!(requeue_interrupted_jobs(&conn).unwrap(), 2);
+        assert_eq!(claim_next_job(&conn).unwrap().unwrap().id, first);
+        finish_job(
+            &conn,
+            first,
+            JobStatus::Succeeded,
+            200,
+            "Peer added: 4242420253",
+        )
+        .unwrap();
+        assert_eq!(claim_next_job(&conn).unwrap().unwrap().id, second);
+        assert!(claim_next_job(&conn).unwrap().is_none());
+
+        let job = get_job(&conn, first).unwrap();
+        assert_eq!(job.status, JobStatus::Succeeded);
+        assert_eq!(job.result_status, Some(200));
+        let log: Vec<&str> = job.log.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            log,
+            [
+                "Queued",
+                "Started",
+                "Requeued after restart",
+                "Started",
+                "succeeded: Peer added: 4242420253"
+            ]
+        );
+        assert!(matches!(get_job(&conn, 999), Err(PeerDbError::NotFound)));
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        let claim = |key: &str, fingerprint: &str| {
+            claim_idempotency_key(&conn, "token", key, fingerprint, 86400).unwrap()
+        };
+        assert_eq!(claim("a", "f1"), IdempotencyClaim::Claimed);
+        assert_eq!(claim("a", "f1"), IdempotencyClaim::InProgress);
+        assert_eq!(claim("a", "f2"), IdempotencyClaim::Mismatch);
+        // key 按身份隔离
+        assert_eq!(
+            claim_idempotency_key(&conn, "uid:0", "a", "f2", 86400).unwrap(),
+            IdempotencyClaim::Claimed
+        );
+
+        let response = StoredResponse {
+            status: 200,
+            content_type: Some("text/plain;charset=utf-8".to_string()),
+            location: Some("/jobs/1".to_string()),
+            body: "Peer added: 4242420253".to_string(),
+        };
+        complete_idempotency_key(&conn, "token", "a", &response).unwrap();
+        assert_eq!(claim("a", "f1"), IdempotencyClaim::Completed(response));
+
+        release_idempotency_key(&conn, "token", "a").unwrap();
+        assert_eq!(claim("a", "f2"), IdempotencyClaim::Claimed);
+        assert_eq!(release_unfinished_idempotency_keys(&conn).unwrap(), 2);
+
+        // 过期的记录被清理
+        claim("b", "f1");
+        complete_idempotency_key(
+            &conn,
+            "token",
+            "b",
+            &StoredResponse {
+                status: 204,
+                content_type: None,
+                location: None,
+                body: String::new(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            claim_idempotency_key(&conn, "token", "b", "f2", -1).unwrap(),
+            IdempotencyClaim::Claimed
+        );
+    }
+}
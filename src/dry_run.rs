@@ -0,0 +1,183 @@
+use crate::gen_config::*;
+use crate::handler::{OpError, PreparedUpdate};
+use crate::model::*;
+use crate::system::*;
+use http_types::StatusCode;
+
+fn internal_error(context: &str, e: anyhow::Error) -> OpError {
+    OpError::new(
+        StatusCode::InternalServerError,
+        format!("{}: {}", context, e),
+    )
+}
+
+fn listen_port(peer: &Peer) -> Result<u16, OpError> {
+    peer.gen_listen_port()
+        .map_err(|e| internal_error("Failed to allocate listen port", e))
+}
+
+// 返回的内容会出现在响应与审计日志中，不包含本端私钥
+fn redact_private_key(wg_config: &str) -> String {
+    wg_config
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("PrivateKey") {
+                "PrivateKey = [REDACTED]"
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn write_file(path: String, content: String) -> PlannedFile {
+    PlannedFile {
+        path,
+        action: FileAction::Write,
+        content: Some(content),
+    }
+}
+
+// 与 update_config_file 的判断一致：内容相同的文件不会被重写
+fn update_file(path: &str, content: &str) -> PlannedFile {
+    let unchanged = read_config_file(path).is_some_and(|current| current == content);
+    PlannedFile {
+        path: path.to_string(),
+        action: if unchanged {
+            FileAction::Unchanged
+        } else {
+            FileAction::Write
+        },
+        content: Some(content.to_string()),
+    }
+}
+
+fn delete_file(path: String) -> PlannedFile {
+    PlannedFile {
+        path,
+        action: FileAction::Delete,
+        content: None,
+    }
+}
+
+fn command_lines(commands: Vec<SystemCommand>) -> Vec<String> {
+    commands.iter().map(ToString::to_string).collect()
+}
+
+// /add：state 为 pending 时只写入数据库，不部署
+pub fn plan_add(
+    peer: &Peer,
+    state: PeerState,
+    message: Option<String>,
+) -> Result<DryRunPlan, OpError> {
+    let interface_name = peer.gen_interface_name();
+    let wg_config = gen_wireguard_config(peer)
+        .map_err(|e| internal_error("Failed to generate WireGuard config", e))?;
+    let bird_config = gen_bird_config(peer, PeerState::Active)
+        .map_err(|e| internal_error("Failed to generate BIRD config", e))?;
+    let (files, commands) = if state == PeerState::Active {
+        let mut commands = apply_commands(&interface_name)
+            .map_err(|e| internal_error("Failed to apply config", e))?;
+        commands.push(reconfigure_command());
+        (
+            vec![
+                write_file(
+                    peer.gen_wireguard_config_path(),
+                    redact_private_key(&wg_config),
+                ),
+                write_file(peer.gen_bird_config_path(), bird_config),
+            ],
+            command_lines(commands),
+        )
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    Ok(DryRunPlan {
+        dry_run: true,
+        action: "add".to_string(),
+        asn: peer.asn,
+        state,
+        listen_port: listen_port(peer)?,
+        interface_name,
+        files,
+        commands,
+        message,
+    })
+}
+
+// /update：只重写内容变化的文件，WireGuard 配置变化时重启 active 的隧道
+pub fn plan_update(prepared: &PreparedUpdate) -> Result<DryRunPlan, OpError> {
+    let mut files = Vec::new();
+    let mut commands = Vec::new();
+    if prepared.state.is_deployed() {
+        let mut wg_file = update_file(&prepared.wg_config_path, &prepared.wg_config);
+        wg_file.content = Some(redact_private_key(&prepared.wg_config));
+        let bird_file = update_file(&prepared.bird_config_path, &prepared.bird_config);
+        if wg_file.action == FileAction::Write && prepared.state == PeerState::Active {
+            commands.extend(
+                restart_commands(&prepared.interface_name)
+                    .map_err(|e| internal_error("Failed to apply config", e))?,
+            );
+        }
+        if bird_file.action == FileAction::Write {
+            commands.push(reconfigure_command());
+        }
+        files.push(wg_file);
+        files.push(bird_file);
+    }
+    Ok(DryRunPlan {
+        dry_run: true,
+        action: "update".to_string(),
+        asn: prepared.peer.asn,
+        state: prepared.state,
+        interface_name: prepared.interface_name.clone(),
+        listen_port: listen_port(&prepared.peer)?,
+        files,
+        commands: command_lines(commands),
+        message: None,
+    })
+}
+
+// /del：未部署的 peer 只删除数据库记录
+pub fn plan_del(info: &PeerDbInfo) -> Result<DryRunPlan, OpError> {
+    let mut files = Vec::new();
+    let mut commands = Vec::new();
+    if info.state.is_deployed() {
+        commands = remove_commands(&info.interface_name, info.state == PeerState::Active)
+            .map_err(|e| internal_error("Failed to remove config", e))?;
+        commands.push(reconfigure_command());
+        if let Some(link_file_path) = openrc_link_path(&info.interface_name) {
+            files.push(delete_file(link_file_path));
+        }
+        files.push(delete_file(info.wireguard_config_path.clone()));
+        files.push(delete_file(info.bird_config_path.clone()));
+    }
+    Ok(DryRunPlan {
+        dry_run: true,
+        action: "del".to_string(),
+        asn: info.asn,
+        state: info.state,
+        interface_name: info.interface_name.clone(),
+        listen_port: listen_port(&Peer::from(info.clone()))?,
+        files,
+        commands: command_lines(commands),
+        message: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_private_key() {
+        let wg_config = "[Interface]\nPrivateKey = c2VjcmV0\nListenPort = 20253\n\n[Peer]\nPublicKey = cHVibGlj";
+        let redacted = redact_private_key(wg_config);
+        assert!(!redacted.contains("c2VjcmV0"));
+        assert_eq!(
+            redacted,
+            "[Interface]\nPrivateKey = [REDACTED]\nListenPort = 20253\n\n[Peer]\nPublicKey = cHVibGlj"
+        );
+    }
+}
@@ -1,19 +1,28 @@
-use crate::CONFIG;
+use crate::config;
 use crate::model::*;
 use anyhow::{Result, anyhow};
 use askama::Template;
 
 pub fn gen_wireguard_config(peer: &Peer) -> Result<String> {
-    let listen_port = match peer.gen_listen_port() {
-        Ok(port) => port,
-        Err(e) => return Err(e),
-    };
+    let config = config();
+    let listen_port = peer.gen_listen_port()?;
+    let allowed_ips = config
+        .peer
+        .allowed_ips
+        .iter()
+        .chain(peer.extra_allowed_ips.iter())
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
     let wg_config = WireguardConfig {
-        wireguard_private_key: CONFIG.peer.wireguard_private_key.clone(),
+        wireguard_private_key: config.peer.wireguard_private_key.clone(),
         wireguard_listen_port: listen_port,
-        wireguard_link_local_ipv6: CONFIG.peer.link_local.clone(),
+        wireguard_link_local_ipv6: config.peer.link_local.clone(),
         wireguard_peer_public_key: peer.wireguard_public_key.clone(),
         wireguard_peer_endpoint: peer.wireguard_endpoint.clone(),
+        wireguard_allowed_ips: allowed_ips,
+        wireguard_mtu: peer.mtu,
+        wireguard_persistent_keepalive: peer.persistent_keepalive,
     };
 
     wg_config
@@ -21,12 +30,13 @@ pub fn gen_wireguard_config(peer: &Peer) -> Result<String> {
         .map_err(|e| anyhow!("Failed to render WireGuard config: {}", e))
 }
 
-pub fn gen_bird_config(peer: &Peer) -> Result<String> {
+pub fn gen_bird_config(peer: &Peer, state: PeerState) -> Result<String> {
     let bird_config = BirdConfig {
         interface_name: peer.gen_interface_name(),
-        wireguard_link_local_ipv6: CONFIG.peer.link_local.clone(),
+        wireguard_link_local_ipv6: config().peer.link_local.clone(),
         peer_link_local_ipv6: peer.wireguard_link_local.clone(),
         peer_asn: peer.asn,
+        disabled: state == PeerState::Disabled,
     };
     bird_config
         .render()
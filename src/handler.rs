@@ -1,35 +1,142 @@
-use crate::CONFIG;
 use crate::Db;
+use crate::adopt::*;
+use crate::approval::*;
+use crate::audit::*;
+use crate::config;
 use crate::db::*;
+use crate::dry_run::*;
 use crate::gen_config::*;
+use crate::idempotency::{idempotency_key, idempotent};
+use crate::jobs::{enqueue_response, handle_get_job, should_queue};
 use crate::model::*;
+use crate::ratelimit::{LIMITER, retry_after_secs};
+use crate::rest::serve_v1;
 use crate::system::*;
+use crate::tls::ClientCertificate;
+use crate::unix_socket::PeerCredentials;
 use http_types::{Method, Request, Response, StatusCode};
+use std::time::{Duration, Instant};
 
+// 逐字节比较全部内容，耗时不随第一个不同字节的位置变化
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[allow(clippy::match_like_matches_macro)]
 fn is_valid_secret(req: &Request) -> bool {
     let secret = req.header("Authorization").and_then(|values| values.get(0));
     match secret {
         Some(content) => match content.as_str().trim().strip_prefix("Bearer ") {
-            Some(key) if key.trim() == CONFIG.api.secret.trim() => true,
+            Some(key)
+                if constant_time_eq(
+                    key.trim().as_bytes(),
+                    config().api.secret.trim().as_bytes(),
+                ) =>
+            {
+                true
+            }
             _ => false,
         },
         None => false,
     }
 }
 
-pub async fn serve_router(req: Request, db: Db) -> http_types::Result<Response> {
-    if !CONFIG.api.secret.trim().is_empty() {
-        if !is_valid_secret(&req) {
-            let mut res = Response::new(StatusCode::Unauthorized);
-            res.insert_header("Content-Type", "text/plain; charset=utf-8");
-            res.set_body("Unauthorized\n".to_string());
-            return Ok(res);
+// 限速与锁定的来源：TCP 连接取对端 IP，Unix socket 连接取对端 uid
+fn request_client(req: &Request) -> String {
+    if let Some(ip) = request_source_ip(req) {
+        ip
+    } else if let Some(cred) = req.ext().get::<PeerCredentials>() {
+        format!("uid:{}", cred.uid)
+    } else {
+        "unknown".to_string()
+    }
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    let mut res = Response::new(StatusCode::TooManyRequests);
+    res.insert_header("Retry-After", retry_after_secs(wait).to_string());
+    res.insert_header("Content-Type", "text/plain; charset=utf-8");
+    res.set_body("Too Many Requests\n".to_string());
+    res
+}
+
+pub async fn serve_router(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let config = config();
+    let client = request_client(&req);
+    let now = Instant::now();
+    if let Err(wait) = LIMITER.check_lockout(&client, now) {
+        return Ok(too_many_requests(wait));
+    }
+    if let Err(wait) = LIMITER.check_client(&config.rate_limit, &client, now) {
+        return Ok(too_many_requests(wait));
+    }
+
+    // 通过客户端证书或受信任 uid 认证的连接无需 bearer token
+    let principal = if let Some(cert) = req.ext().get::<ClientCertificate>() {
+        format!("mtls:{}", cert.0)
+    } else if let Some(cred) = req.ext().get::<PeerCredentials>()
+        && config.unix_socket.trusted_uids.contains(&cred.uid)
+    {
+        format!("uid:{}", cred.uid)
+    } else if config.api.secret.trim().is_empty() {
+        "anonymous".to_string()
+    } else if is_valid_secret(&req) {
+        LIMITER.record_auth_success(&client);
+        "token".to_string()
+    } else {
+        if LIMITER.record_auth_failure(&config.rate_limit, &client, now) {
+            eprintln!(
+                "Locking out {} for {}s after {} failed authentication attempts",
+                client, config.rate_limit.lockout_seconds, config.rate_limit.max_failed_auth
+            );
         }
+        let mut res = Response::new(StatusCode::Unauthorized);
+        res.insert_header("Content-Type", "text/plain; charset=utf-8");
+        res.set_body("Unauthorized\n".to_string());
+        return Ok(res);
+    };
+    if let Err(wait) = LIMITER.check_principal(&config.rate_limit, &principal, now) {
+        return Ok(too_many_requests(wait));
     }
+    req.ext_mut().insert(Principal(principal));
+
+    match idempotency_key(&req) {
+        Some(key) => idempotent(key, req, db, route).await,
+        None => route(req, db).await,
+    }
+}
+
+async fn route(req: Request, db: Db) -> http_types::Result<Response> {
     match (req.method(), req.url().path()) {
-        (Method::Post, "/add") => handle_add(req, db).await,
+        (Method::Post, "/add") => audited("add", req, db, handle_add).await,
         (Method::Post, "/get") => handle_get(req, db).await,
-        (Method::Post, "/del") => handle_del(req, db).await,
+        (Method::Post, "/del") => audited("del", req, db, handle_del).await,
+        (Method::Post, "/update") => audited("update", req, db, handle_update).await,
+        (Method::Post, "/disable") => {
+            audited("disable", req, db, |req, db| {
+                handle_set_state(req, db, PeerState::Disabled)
+            })
+            .await
+        }
+        (Method::Post, "/enable") => {
+            audited("enable", req, db, |req, db| {
+                handle_set_state(req, db, PeerState::Active)
+            })
+            .await
+        }
+        (Method::Post, "/approve") => audited("approve", req, db, handle_approve).await,
+        (Method::Post, "/reject") => audited("reject", req, db, handle_reject).await,
+        (Method::Post, "/list") => handle_list(req, db).await,
+        (Method::Post, "/export") => handle_export(req, db).await,
+        (Method::Post, "/import") => audited("import", req, db, handle_import).await,
+        (Method::Post, "/adopt") => audited("adopt", req, db, handle_adopt).await,
+        (Method::Post, "/resync") => audited("resync", req, db, |_, db| handle_resync(db)).await,
+        (Method::Post, "/audit") => handle_audit(req, db).await,
+        (Method::Get, path) if path.starts_with("/jobs/") => handle_get_job(req, db).await,
+        (_, path) if path.starts_with("/v1/") => serve_v1(req, db).await,
 
         _ => {
             let mut res = Response::new(StatusCode::NotFound);
@@ -40,115 +147,283 @@ pub async fn serve_router(req: Request, db: Db) -> http_types::Result<Response>
     }
 }
 
-pub async fn handle_add(mut req: Request, db: Db) -> http_types::Result<Response> {
-    let mut req_peer: Peer = match req.body_json().await {
-        Ok(data) => data,
-        Err(e) => {
-            eprintln!("Failed to parse JSON: {}", e);
-            let mut res = Response::new(StatusCode::BadRequest);
-            res.set_body(format!("Invalid JSON: {}", e));
-            return Ok(res);
+// 数据库中记录的配置文件路径；Paths 在运行中修改后，已有 peer 的文件仍写在原来的位置
+#[derive(Clone, Debug)]
+pub struct ConfigPaths {
+    pub wireguard: String,
+    pub bird: String,
+}
+
+impl From<&PeerDbInfo> for ConfigPaths {
+    fn from(info: &PeerDbInfo) -> Self {
+        ConfigPaths {
+            wireguard: info.wireguard_config_path.clone(),
+            bird: info.bird_config_path.clone(),
         }
-    };
+    }
+}
 
-    if !req_peer.is_valid_wireguard_endpoint() {
-        let mut res = Response::new(StatusCode::BadRequest);
-        res.set_body("Invalid Wireguard endpoint".to_string());
-        return Ok(res);
+// 读取刚写入数据库的 peer 记录的配置文件路径
+async fn stored_paths(asn: u64, db: Db) -> Result<ConfigPaths, String> {
+    match fetch_peer(asn, db).await {
+        Ok(Some(info)) => Ok(ConfigPaths::from(&info)),
+        Ok(None) => Err(format!("Peer not found: {}", asn)),
+        Err(e) => Err(e.message),
     }
-    if !req_peer.is_valid_asn() {
-        let mut res = Response::new(StatusCode::BadRequest);
-        res.set_body("Invalid ASN".to_string());
-        return Ok(res);
+}
+
+// 生成并写入配置文件，启动隧道并重新加载 BIRD
+pub async fn deploy_peer(peer: Peer, paths: ConfigPaths, reload: BirdReload) -> Result<(), String> {
+    smol::unblock(move || {
+        let wg_config = gen_wireguard_config(&peer)
+            .map_err(|e| format!("Failed to generate WireGuard config: {}", e))?;
+        let bird_config = gen_bird_config(&peer, PeerState::Active)
+            .map_err(|e| format!("Failed to generate BIRD config: {}", e))?;
+        save_config(&paths.wireguard, &wg_config, &paths.bird, &bird_config)
+            .map_err(|e| format!("Failed to save config: {}", e))?;
+        apply_config(&peer.gen_interface_name())
+            .and_then(|_| reload.request())
+            .map_err(|e| format!("Failed to apply config: {}", e))
+    })
+    .await
+}
+
+// 部署 pending 状态的 peer，成功后标记为 active；失败时保持 pending 以便重试
+async fn approve_pending_peer(
+    peer: Peer,
+    paths: ConfigPaths,
+    db: Db,
+    reload: BirdReload,
+) -> Result<(), String> {
+    deploy_peer(peer.clone(), paths, reload).await?;
+    smol::unblock(move || match db.lock() {
+        Ok(conn) => set_peer_state(&conn, peer.asn, PeerState::Active),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await
+    .map(|_| ())
+    .map_err(|e| format!("Database error: {}", e))
+}
+
+// 带 HTTP 状态码的操作错误，供 /add 与 /import 共用
+#[derive(Debug)]
+pub struct OpError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl OpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        OpError {
+            status,
+            message: message.into(),
+        }
     }
-    req_peer.wireguard_link_local_strip_cidr();
-    if !req_peer.is_valid_link_local() {
-        let mut res = Response::new(StatusCode::BadRequest);
-        res.set_body("Invalid Link-Local address".to_string());
-        return Ok(res);
+
+    pub fn into_response(self) -> Response {
+        let mut res = Response::new(self.status);
+        res.set_body(self.message);
+        res
     }
-    if !req_peer.is_valid_wireguard_public_key() {
-        let mut res = Response::new(StatusCode::BadRequest);
-        res.set_body("Invalid Wireguard public key".to_string());
-        return Ok(res);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddOutcome {
+    Added,
+    Pending,
+    // the peer already exists with exactly the same fields
+    Unchanged,
+    // dry run: validation passed, nothing was written
+    WouldAdd(DryRunPlan),
+}
+
+// 重复提交与现有 peer 完全相同的内容视为成功，其他情况仍为冲突
+// 现有 peer 为 active 时重新部署，使在部署完成前中断的上一次请求得以收敛
+async fn readd_existing(
+    peer: Peer,
+    db: Db,
+    dry_run: bool,
+    reload: BirdReload,
+) -> Result<AddOutcome, OpError> {
+    let conflict = || {
+        OpError::new(
+            StatusCode::Conflict,
+            format!("Peer already exists: {}", peer.asn),
+        )
+    };
+    let Some(info) = fetch_peer(peer.asn, db).await? else {
+        return Err(conflict());
+    };
+    let state = info.state;
+    let paths = ConfigPaths::from(&info);
+    if state == PeerState::Rejected || Peer::from(info) != peer {
+        return Err(conflict());
+    }
+    match state {
+        PeerState::Pending => Ok(AddOutcome::Pending),
+        PeerState::Active if !dry_run => {
+            deploy_peer(peer.clone(), paths, reload)
+                .await
+                .map_err(|msg| OpError::new(StatusCode::InternalServerError, msg))?;
+            println!("Peer already added, redeployed: {}", peer.asn);
+            Ok(AddOutcome::Unchanged)
+        }
+        _ => Ok(AddOutcome::Unchanged),
     }
+}
 
-    let req_peer_clone = req_peer.clone();
+// /add 的完整流程：校验、写入数据库、审批检查、部署
+pub async fn add_peer_flow(
+    mut peer: Peer,
+    db: Db,
+    dry_run: bool,
+    reload: BirdReload,
+) -> Result<AddOutcome, OpError> {
+    peer.validate()
+        .map_err(|msg| OpError::new(StatusCode::BadRequest, msg))?;
 
-    let db_result: Result<(), PeerDbError> = smol::unblock(move || match db.lock() {
-        Ok(conn) => match add_peer(&conn, &req_peer_clone) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        },
+    let state = if config().approval.require_approval {
+        PeerState::Pending
+    } else {
+        PeerState::Active
+    };
+
+    if dry_run {
+        let existing = fetch_peer(peer.asn, db.clone()).await?;
+        if existing.is_some_and(|info| info.state != PeerState::Rejected) {
+            return readd_existing(peer, db, true, reload).await;
+        }
+        // 与实际执行相同，pending 的请求先经过自动批准检查
+        let (state, message) = if state == PeerState::Pending {
+            let check_peer = peer.clone();
+            match smol::unblock(move || auto_approve_check(&check_peer)).await {
+                Ok(()) => (
+                    PeerState::Active,
+                    Some("Would be auto-approved".to_string()),
+                ),
+                Err(reason) => (
+                    PeerState::Pending,
+                    Some(format!("Would be pending approval: {}", reason)),
+                ),
+            }
+        } else {
+            (state, None)
+        };
+        let plan = smol::unblock(move || plan_add(&peer, state, message)).await?;
+        return Ok(AddOutcome::WouldAdd(plan));
+    }
+
+    let peer_clone = peer.clone();
+    let db_clone = db.clone();
+    let db_result: Result<usize, PeerDbError> = smol::unblock(move || match db_clone.lock() {
+        Ok(conn) => add_peer(&conn, &peer_clone, state),
         Err(e) => Err(PeerDbError::LockError(e.to_string())),
     })
     .await;
 
     match db_result {
-        Ok(_) => match gen_wireguard_config(&req_peer) {
-            Ok(wg_config) => match gen_bird_config(&req_peer) {
-                Ok(bird_config) => {
-                    match save_config(
-                        &req_peer.gen_wireguard_config_path(),
-                        &wg_config,
-                        &req_peer.gen_bird_config_path(),
-                        &bird_config,
-                    ) {
-                        Ok(_) => match apply_config(&req_peer.gen_interface_name()) {
-                            Ok(_) => {
-                                println!("Peer added: {}", req_peer.asn);
-                                let mut res = Response::new(StatusCode::Ok);
-                                res.set_body(format!("Peer added: {}", req_peer.asn));
-                                Ok(res)
-                            }
-                            Err(e) => {
-                                let mut res = Response::new(StatusCode::InternalServerError);
-                                res.set_body(format!("Failed to apply config: {}", e));
-                                return Ok(res);
-                            }
-                        },
-                        Err(e) => {
-                            let mut res = Response::new(StatusCode::InternalServerError);
-                            res.set_body(format!("Failed to save config: {}", e));
-                            return Ok(res);
-                        }
-                    }
-                }
-                Err(e) => {
-                    let mut res = Response::new(StatusCode::InternalServerError);
-                    res.set_body(format!("Failed to generate BIRD config: {}", e));
-                    return Ok(res);
-                }
-            },
-            Err(e) => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Failed to generate WireGuard config: {}", e));
-                return Ok(res);
-            }
-        },
-        Err(e) => match e {
-            PeerDbError::AlreadyExist => {
-                let mut res = Response::new(StatusCode::Conflict);
-                res.set_body(format!("Peer already exists: {}", req_peer.asn));
-                Ok(res)
-            }
+        Ok(_) => {}
+        Err(PeerDbError::AlreadyExist) => {
+            return readd_existing(peer, db, false, reload).await;
+        }
+        Err(PeerDbError::RusqliteError(err_string)) => {
+            return Err(OpError::new(
+                StatusCode::InternalServerError,
+                format!("Database error: {}", err_string),
+            ));
+        }
+        Err(e) => {
+            return Err(OpError::new(
+                StatusCode::InternalServerError,
+                format!("Unknown error: {}", e),
+            ));
+        }
+    }
 
-            PeerDbError::RusqliteError(err_string) => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Database error: {}", err_string));
+    if state == PeerState::Pending {
+        let check_peer = peer.clone();
+        if let Err(reason) = smol::unblock(move || auto_approve_check(&check_peer)).await {
+            println!("Peer pending approval: {} ({})", peer.asn, reason);
+            return Ok(AddOutcome::Pending);
+        }
+        let paths = stored_paths(peer.asn, db.clone())
+            .await
+            .map_err(|msg| OpError::new(StatusCode::InternalServerError, msg))?;
+        approve_pending_peer(peer.clone(), paths, db, reload)
+            .await
+            .map_err(|msg| OpError::new(StatusCode::InternalServerError, msg))?;
+        println!("Peer auto-approved and added: {}", peer.asn);
+        return Ok(AddOutcome::Added);
+    }
+
+    let paths = stored_paths(peer.asn, db)
+        .await
+        .map_err(|msg| OpError::new(StatusCode::InternalServerError, msg))?;
+    deploy_peer(peer.clone(), paths, reload)
+        .await
+        .map_err(|msg| OpError::new(StatusCode::InternalServerError, msg))?;
+    println!("Peer added: {}", peer.asn);
+    Ok(AddOutcome::Added)
+}
+
+pub async fn handle_add(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let mut req_peer: Peer = match req.body_json().await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid JSON: {}", e));
+            return Ok(res);
+        }
+    };
+    let asn = req_peer.asn;
+
+    if is_dry_run(&req) {
+        return match add_peer_flow(req_peer, db, true, BirdReload::Immediate).await {
+            Ok(AddOutcome::WouldAdd(plan)) => plan_response(Ok(plan)),
+            Ok(AddOutcome::Pending) => {
+                let mut res = Response::new(StatusCode::Accepted);
+                res.set_body(format!("Peer pending approval: {}", asn));
                 Ok(res)
             }
-
-            _ => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Unknown error: {}", e));
+            Ok(_) => {
+                let mut res = Response::new(StatusCode::Ok);
+                res.set_body(format!("Peer already added: {}", asn));
                 Ok(res)
             }
-        },
+            Err(e) => Ok(e.into_response()),
+        };
+    }
+    if should_queue(&req) {
+        if let Err(msg) = req_peer.validate() {
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(msg);
+            return Ok(res);
+        }
+        return enqueue_response(&req, JobAction::Add, asn, &req_peer, db).await;
+    }
+
+    match add_peer_flow(req_peer, db, false, BirdReload::Immediate).await {
+        Ok(AddOutcome::Pending) => {
+            let mut res = Response::new(StatusCode::Accepted);
+            res.set_body(format!("Peer pending approval: {}", asn));
+            Ok(res)
+        }
+        Ok(AddOutcome::Unchanged) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(format!("Peer already added: {}", asn));
+            Ok(res)
+        }
+        Ok(_) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(format!("Peer added: {}", asn));
+            Ok(res)
+        }
+        Err(e) => Ok(e.into_response()),
     }
 }
 
-pub async fn handle_del(mut req: Request, db: Db) -> http_types::Result<Response> {
+pub async fn handle_approve(mut req: Request, db: Db) -> http_types::Result<Response> {
     let req_peer: Peer = match req.body_json().await {
         Ok(data) => data,
         Err(e) => {
@@ -158,99 +433,57 @@ pub async fn handle_del(mut req: Request, db: Db) -> http_types::Result<Response
             return Ok(res);
         }
     };
+    let asn = req_peer.asn;
 
-    // del 操作传入的 req_peer 只有 asn，需要从数据库中获取完整的 Peer 对象
     let db_clone = db.clone();
     let peer_result: Result<PeerDbInfo, PeerDbError> =
         smol::unblock(move || match db_clone.lock() {
-            Ok(conn) => match get_peer_by_asn(&conn, req_peer.asn) {
-                Ok(peer) => Ok(peer),
-                Err(e) => Err(e),
-            },
+            Ok(conn) => get_peer_by_asn(&conn, asn),
             Err(e) => Err(PeerDbError::LockError(e.to_string())),
         })
         .await;
 
-    match peer_result {
-        Ok(peer) => {
-            // 获取完整 peer 信息成功，开始移除 peer
-            match remove_config(
-                &peer.interface_name,
-                &peer.wireguard_config_path,
-                &peer.bird_config_path,
-            ) {
-                Ok(_) => {
-                    // peer removed, clean database
-                    let db_result: Result<(), PeerDbError> =
-                        smol::unblock(move || match db.lock() {
-                            Ok(conn) => match delete_peer_by_asn(&conn, req_peer.asn) {
-                                Ok(_) => Ok(()),
-                                Err(e) => Err(e),
-                            },
-                            Err(e) => Err(PeerDbError::LockError(e.to_string())),
-                        })
-                        .await;
-
-                    match db_result {
-                        Ok(_) => {
-                            println!("Peer deleted: {}", req_peer.asn);
-                            let mut res = Response::new(StatusCode::Ok);
-                            res.set_body(format!("Peer deleted: {}", req_peer.asn));
-                            Ok(res)
-                        }
-                        Err(e) => match e {
-                            PeerDbError::NotFound => {
-                                let mut res = Response::new(StatusCode::BadRequest);
-                                res.set_body(format!("Peer not found: {}", req_peer.asn));
-                                Ok(res)
-                            }
-
-                            PeerDbError::RusqliteError(err_string) => {
-                                let mut res = Response::new(StatusCode::InternalServerError);
-                                res.set_body(format!("Database error: {}", err_string));
-                                Ok(res)
-                            }
-
-                            _ => {
-                                let mut res = Response::new(StatusCode::InternalServerError);
-                                res.set_body(format!("Unknown error: {}", e));
-                                Ok(res)
-                            }
-                        },
-                    }
-                }
-                Err(e) => {
-                    let mut res = Response::new(StatusCode::InternalServerError);
-                    res.set_body(format!("error: {}", e));
-                    Ok(res)
-                }
-            }
+    let info = match peer_result {
+        Ok(info) => info,
+        Err(PeerDbError::NotFound) => {
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Peer not found: {}", asn));
+            return Ok(res);
         }
-        // failed to get peer from db
-        Err(e) => match e {
-            PeerDbError::NotFound => {
-                let mut res = Response::new(StatusCode::BadRequest);
-                res.set_body(format!("Peer not found: {}", req_peer.asn));
-                Ok(res)
-            }
-
-            PeerDbError::RusqliteError(err_string) => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Database error: {}", err_string));
-                Ok(res)
-            }
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            return Ok(res);
+        }
+    };
+    if info.state != PeerState::Pending {
+        let mut res = Response::new(StatusCode::Conflict);
+        res.set_body(format!(
+            "Peer is {}, not pending: {}",
+            info.state.as_str(),
+            asn
+        ));
+        return Ok(res);
+    }
 
-            _ => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Unknown error: {}", e));
-                Ok(res)
-            }
-        },
+    let paths = ConfigPaths::from(&info);
+    match approve_pending_peer(Peer::from(info), paths, db, BirdReload::Immediate).await {
+        Ok(_) => {
+            println!("Peer approved: {}", asn);
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(format!("Peer approved: {}", asn));
+            Ok(res)
+        }
+        Err(msg) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(msg);
+            Ok(res)
+        }
     }
 }
 
-pub async fn handle_get(mut req: Request, db: Db) -> http_types::Result<Response> {
-    let req_peer: Peer = match req.body_json().await {
+pub async fn handle_reject(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let reject: RejectRequest = match req.body_json().await {
         Ok(data) => data,
         Err(e) => {
             eprintln!("Failed to parse JSON: {}", e);
@@ -259,55 +492,1616 @@ pub async fn handle_get(mut req: Request, db: Db) -> http_types::Result<Response
             return Ok(res);
         }
     };
+    let asn = reject.asn;
 
-    let db_result: Result<Peer, PeerDbError> = smol::unblock(move || match db.lock() {
-        Ok(conn) => match get_peer_by_asn(&conn, req_peer.asn) {
-            Ok(peer) => Ok(Peer {
-                asn: peer.asn,
-                wireguard_endpoint: peer.wireguard_endpoint.clone(),
-                wireguard_link_local: peer.wireguard_link_local.clone(),
-                wireguard_public_key: peer.wireguard_public_key.clone(),
-            }),
-            Err(e) => Err(e),
-        },
+    let db_result: Result<PeerState, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => {
+            let info = get_peer_by_asn(&conn, reject.asn)?;
+            if info.state == PeerState::Pending {
+                reject_peer(&conn, reject.asn, &reject.reason)?;
+            }
+            Ok(info.state)
+        }
         Err(e) => Err(PeerDbError::LockError(e.to_string())),
     })
     .await;
 
     match db_result {
-        Ok(peer) => {
+        Ok(PeerState::Pending) => {
+            println!("Peer rejected: {}", asn);
             let mut res = Response::new(StatusCode::Ok);
-            match serde_json::to_string(&peer) {
-                Ok(json_response) => {
-                    res.insert_header("Content-Type", "application/json; charset=utf-8");
-                    res.set_body(json_response);
-                }
-                Err(e) => {
-                    eprintln!("Failed to serialize peer: {}", e);
-                    let mut res = Response::new(StatusCode::InternalServerError);
-                    res.set_body(format!("Failed to serialize peer: {}", e));
-                }
-            }
+            res.set_body(format!("Peer rejected: {}", asn));
             Ok(res)
         }
-        Err(e) => match e {
-            PeerDbError::NotFound => {
+        Ok(state) => {
+            let mut res = Response::new(StatusCode::Conflict);
+            res.set_body(format!("Peer is {}, not pending: {}", state.as_str(), asn));
+            Ok(res)
+        }
+        Err(PeerDbError::NotFound) => {
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Peer not found: {}", asn));
+            Ok(res)
+        }
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            Ok(res)
+        }
+    }
+}
+
+pub async fn handle_list(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let body = req.body_string().await?;
+    let list: ListRequest = if body.trim().is_empty() {
+        ListRequest::default()
+    } else {
+        match serde_json::from_str(&body) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to parse JSON: {}", e);
                 let mut res = Response::new(StatusCode::BadRequest);
-                res.set_body(format!("Peer not found: {}", req_peer.asn));
-                Ok(res)
+                res.set_body(format!("Invalid JSON: {}", e));
+                return Ok(res);
             }
+        }
+    };
 
-            PeerDbError::RusqliteError(err_string) => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Database error: {}", err_string));
-                Ok(res)
-            }
+    let db_result: Result<Vec<PeerDbInfo>, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => list_peers(&conn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
 
-            _ => {
-                let mut res = Response::new(StatusCode::InternalServerError);
-                res.set_body(format!("Unknown error: {}", e));
-                Ok(res)
-            }
-        },
+    match db_result {
+        Ok(peers) => {
+            let peers: Vec<PeerDbInfo> = peers
+                .into_iter()
+                .filter(|p| list.state.is_none_or(|state| p.state == state))
+                .collect();
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header("Content-Type", "application/json; charset=utf-8");
+            res.set_body(serde_json::to_string(&peers)?);
+            Ok(res)
+        }
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            Ok(res)
+        }
+    }
+}
+
+fn db_error(e: PeerDbError) -> OpError {
+    match e {
+        PeerDbError::RusqliteError(err_string) => OpError::new(
+            StatusCode::InternalServerError,
+            format!("Database error: {}", err_string),
+        ),
+        _ => OpError::new(
+            StatusCode::InternalServerError,
+            format!("Unknown error: {}", e),
+        ),
+    }
+}
+
+pub async fn del_peer_flow(asn: u64, db: Db, reload: BirdReload) -> Result<(), OpError> {
+    // del 操作只需要 asn，需要从数据库中获取完整的 peer 信息
+    let db_clone = db.clone();
+    let peer_result: Result<PeerDbInfo, PeerDbError> =
+        smol::unblock(move || match db_clone.lock() {
+            Ok(conn) => get_peer_by_asn(&conn, asn),
+            Err(e) => Err(PeerDbError::LockError(e.to_string())),
+        })
+        .await;
+
+    let peer = match peer_result {
+        Ok(peer) => peer,
+        Err(PeerDbError::NotFound) => {
+            return Err(OpError::new(
+                StatusCode::BadRequest,
+                format!("Peer not found: {}", asn),
+            ));
+        }
+        Err(e) => return Err(db_error(e)),
+    };
+
+    // 获取完整 peer 信息成功，开始移除 peer；未部署的 peer 只需删除数据库记录
+    if peer.state.is_deployed() {
+        smol::unblock(move || {
+            remove_config(
+                &peer.interface_name,
+                &peer.wireguard_config_path,
+                &peer.bird_config_path,
+                peer.state == PeerState::Active,
+            )
+            .and_then(|_| reload.request())
+        })
+        .await
+        .map_err(|e| OpError::new(StatusCode::InternalServerError, format!("error: {}", e)))?;
+    }
+
+    // peer removed, clean database
+    let db_result: Result<usize, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => delete_peer_by_asn(&conn, asn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+
+    match db_result {
+        Ok(_) => {
+            println!("Peer deleted: {}", asn);
+            Ok(())
+        }
+        Err(PeerDbError::NotFound) => Err(OpError::new(
+            StatusCode::BadRequest,
+            format!("Peer not found: {}", asn),
+        )),
+        Err(e) => Err(db_error(e)),
+    }
+}
+
+pub async fn plan_del_flow(asn: u64, db: Db) -> Result<DryRunPlan, OpError> {
+    match fetch_peer(asn, db).await? {
+        Some(info) => plan_del(&info),
+        None => Err(OpError::new(
+            StatusCode::BadRequest,
+            format!("Peer not found: {}", asn),
+        )),
+    }
+}
+
+pub async fn handle_del(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let req_peer: Peer = match req.body_json().await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid JSON: {}", e));
+            return Ok(res);
+        }
+    };
+
+    if is_dry_run(&req) {
+        return plan_response(plan_del_flow(req_peer.asn, db).await);
+    }
+    if should_queue(&req) {
+        match fetch_peer(req_peer.asn, db.clone()).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let mut res = Response::new(StatusCode::BadRequest);
+                res.set_body(format!("Peer not found: {}", req_peer.asn));
+                return Ok(res);
+            }
+            Err(e) => return Ok(e.into_response()),
+        }
+        return enqueue_response(
+            &req,
+            JobAction::Del,
+            req_peer.asn,
+            &serde_json::json!({ "asn": req_peer.asn }),
+            db,
+        )
+        .await;
+    }
+
+    match del_peer_flow(req_peer.asn, db, BirdReload::Immediate).await {
+        Ok(_) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(format!("Peer deleted: {}", req_peer.asn));
+            Ok(res)
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+pub async fn handle_get(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let req_peer: Peer = match req.body_json().await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid JSON: {}", e));
+            return Ok(res);
+        }
+    };
+
+    let db_result: Result<PeerDbInfo, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => get_peer_by_asn(&conn, req_peer.asn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+
+    match db_result {
+        Ok(peer) => {
+            let mut res = Response::new(StatusCode::Ok);
+            match serde_json::to_string(&peer) {
+                Ok(json_response) => {
+                    res.insert_header("Content-Type", "application/json; charset=utf-8");
+                    res.set_body(json_response);
+                }
+                Err(e) => {
+                    eprintln!("Failed to serialize peer: {}", e);
+                    let mut res = Response::new(StatusCode::InternalServerError);
+                    res.set_body(format!("Failed to serialize peer: {}", e));
+                }
+            }
+            Ok(res)
+        }
+        Err(e) => match e {
+            PeerDbError::NotFound => {
+                let mut res = Response::new(StatusCode::BadRequest);
+                res.set_body(format!("Peer not found: {}", req_peer.asn));
+                Ok(res)
+            }
+
+            PeerDbError::RusqliteError(err_string) => {
+                let mut res = Response::new(StatusCode::InternalServerError);
+                res.set_body(format!("Database error: {}", err_string));
+                Ok(res)
+            }
+
+            _ => {
+                let mut res = Response::new(StatusCode::InternalServerError);
+                res.set_body(format!("Unknown error: {}", e));
+                Ok(res)
+            }
+        },
+    }
+}
+
+// 读取单个 peer，不存在时返回 None
+pub async fn fetch_peer(asn: u64, db: Db) -> Result<Option<PeerDbInfo>, OpError> {
+    let db_result: Result<PeerDbInfo, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => get_peer_by_asn(&conn, asn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    match db_result {
+        Ok(info) => Ok(Some(info)),
+        Err(PeerDbError::NotFound) => Ok(None),
+        Err(e) => Err(db_error(e)),
+    }
+}
+
+// /update 合并修改、校验并渲染配置后的结果，dry run 与实际执行共用
+pub struct PreparedUpdate {
+    pub peer: Peer,
+    pub state: PeerState,
+    pub interface_name: String,
+    pub wg_config_path: String,
+    pub wg_config: String,
+    pub bird_config_path: String,
+    pub bird_config: String,
+}
+
+async fn prepare_update(update: PeerUpdate, db: Db) -> Result<PreparedUpdate, OpError> {
+    let asn = update.asn;
+    let info = match fetch_peer(asn, db).await {
+        Ok(Some(info)) => info,
+        Ok(None) => {
+            return Err(OpError::new(
+                StatusCode::BadRequest,
+                format!("Peer not found: {}", asn),
+            ));
+        }
+        Err(e) => return Err(e),
+    };
+    let interface_name = info.interface_name.clone();
+    let wg_config_path = info.wireguard_config_path.clone();
+    let bird_config_path = info.bird_config_path.clone();
+    let state = info.state;
+    let mut peer = Peer::from(info);
+    update.apply_to(&mut peer);
+    peer.validate()
+        .map_err(|msg| OpError::new(StatusCode::BadRequest, msg))?;
+
+    let wg_config = gen_wireguard_config(&peer).map_err(|e| {
+        OpError::new(
+            StatusCode::InternalServerError,
+            format!("Failed to generate WireGuard config: {}", e),
+        )
+    })?;
+    let bird_config = gen_bird_config(&peer, state).map_err(|e| {
+        OpError::new(
+            StatusCode::InternalServerError,
+            format!("Failed to generate BIRD config: {}", e),
+        )
+    })?;
+    Ok(PreparedUpdate {
+        peer,
+        state,
+        interface_name,
+        wg_config_path,
+        wg_config,
+        bird_config_path,
+        bird_config,
+    })
+}
+
+// /update 的完整流程：合并修改、校验、写入数据库，仅在配置变化时重启隧道或重新加载 BIRD
+pub async fn update_peer_flow(
+    update: PeerUpdate,
+    db: Db,
+    reload: BirdReload,
+) -> Result<(), OpError> {
+    let PreparedUpdate {
+        peer,
+        state,
+        interface_name,
+        wg_config_path,
+        wg_config,
+        bird_config_path,
+        bird_config,
+    } = prepare_update(update, db.clone()).await?;
+
+    let peer_clone = peer.clone();
+    let db_result: Result<usize, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => update_peer(&conn, &peer_clone),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = db_result {
+        return Err(OpError::new(
+            StatusCode::InternalServerError,
+            format!("Database error: {}", e),
+        ));
+    }
+    if !state.is_deployed() {
+        println!("Peer updated: {}", peer.asn);
+        return Ok(());
+    }
+
+    // 已禁用的隧道不会被启动
+    smol::unblock(move || -> anyhow::Result<()> {
+        if update_config_file(&wg_config_path, &wg_config)? && state == PeerState::Active {
+            restart_tunnel(&interface_name)?;
+        }
+        if update_config_file(&bird_config_path, &bird_config)? {
+            reload.request()?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| {
+        OpError::new(
+            StatusCode::InternalServerError,
+            format!("Failed to apply config: {}", e),
+        )
+    })?;
+    println!("Peer updated: {}", peer.asn);
+    Ok(())
+}
+
+// /update 的 dry run：读取现有配置文件判断哪些文件会被重写
+// 入队前按同步路径的规则校验 /update，出错时返回相同的状态码
+pub async fn validate_update(update: &PeerUpdate, db: Db) -> Result<(), OpError> {
+    prepare_update(update.clone(), db).await.map(|_| ())
+}
+
+pub async fn plan_update_flow(update: PeerUpdate, db: Db) -> Result<DryRunPlan, OpError> {
+    let prepared = prepare_update(update, db).await?;
+    smol::unblock(move || plan_update(&prepared)).await
+}
+
+pub async fn handle_update(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let update: PeerUpdate = match req.body_json().await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid JSON: {}", e));
+            return Ok(res);
+        }
+    };
+    let asn = update.asn;
+
+    if is_dry_run(&req) {
+        return plan_response(plan_update_flow(update, db).await);
+    }
+    if should_queue(&req) {
+        if let Err(e) = validate_update(&update, db.clone()).await {
+            return Ok(e.into_response());
+        }
+        return enqueue_response(&req, JobAction::Update, asn, &update, db).await;
+    }
+
+    match update_peer_flow(update, db, BirdReload::Immediate).await {
+        Ok(()) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(format!("Peer updated: {}", asn));
+            Ok(res)
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+// 禁用或重新启用 peer，保留数据库记录，返回结果描述
+pub async fn set_state_flow(asn: u64, target: PeerState, db: Db) -> Result<String, OpError> {
+    let db_clone = db.clone();
+    let peer_result: Result<PeerDbInfo, PeerDbError> =
+        smol::unblock(move || match db_clone.lock() {
+            Ok(conn) => get_peer_by_asn(&conn, asn),
+            Err(e) => Err(PeerDbError::LockError(e.to_string())),
+        })
+        .await;
+
+    let info = match peer_result {
+        Ok(info) => info,
+        Err(PeerDbError::NotFound) => {
+            return Err(OpError::new(
+                StatusCode::BadRequest,
+                format!("Peer not found: {}", asn),
+            ));
+        }
+        Err(e) => {
+            return Err(OpError::new(
+                StatusCode::InternalServerError,
+                format!("Database error: {}", e),
+            ));
+        }
+    };
+
+    if info.state == target {
+        return Ok(format!("Peer already {}: {}", target.as_str(), asn));
+    }
+    if !info.state.is_deployed() {
+        return Err(OpError::new(
+            StatusCode::Conflict,
+            format!("Peer is {}: {}", info.state.as_str(), asn),
+        ));
+    }
+
+    let interface_name = info.interface_name.clone();
+    let bird_config_path = info.bird_config_path.clone();
+    let peer = Peer::from(info);
+    let bird_config = gen_bird_config(&peer, target).map_err(|e| {
+        OpError::new(
+            StatusCode::InternalServerError,
+            format!("Failed to generate BIRD config: {}", e),
+        )
+    })?;
+
+    // BIRD 配置中的 disabled 标记保证 BIRD 重启后状态不变
+    smol::unblock(move || -> anyhow::Result<()> {
+        update_config_file(&bird_config_path, &bird_config)?;
+        if target == PeerState::Disabled {
+            disable_peer(&interface_name)
+        } else {
+            enable_peer(&interface_name)
+        }
+    })
+    .await
+    .map_err(|e| {
+        OpError::new(
+            StatusCode::InternalServerError,
+            format!("Failed to apply config: {}", e),
+        )
+    })?;
+
+    let db_result: Result<usize, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => set_peer_state(&conn, asn, target),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = db_result {
+        return Err(OpError::new(
+            StatusCode::InternalServerError,
+            format!("Database error: {}", e),
+        ));
+    }
+
+    println!("Peer {}: {}", target.as_str(), asn);
+    Ok(format!("Peer {}: {}", target.as_str(), asn))
+}
+
+pub async fn handle_set_state(
+    mut req: Request,
+    db: Db,
+    target: PeerState,
+) -> http_types::Result<Response> {
+    let req_peer: Peer = match req.body_json().await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid JSON: {}", e));
+            return Ok(res);
+        }
+    };
+
+    match set_state_flow(req_peer.asn, target, db).await {
+        Ok(msg) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(msg);
+            Ok(res)
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+pub fn query_param(req: &Request, name: &str) -> Option<String> {
+    req.url()
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+// ?dry_run=true 只校验并返回将要执行的操作，不做任何修改
+pub fn is_dry_run(req: &Request) -> bool {
+    matches!(query_param(req, "dry_run").as_deref(), Some("true" | "1"))
+}
+
+pub fn plan_response(plan: Result<DryRunPlan, OpError>) -> http_types::Result<Response> {
+    match plan {
+        Ok(plan) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header("Content-Type", "application/json; charset=utf-8");
+            res.set_body(serde_json::to_string(&plan)?);
+            Ok(res)
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+fn is_toml_format(req: &Request) -> bool {
+    query_param(req, "format").as_deref() == Some("toml")
+}
+
+pub async fn handle_export(req: Request, db: Db) -> http_types::Result<Response> {
+    let db_result: Result<Vec<PeerDbInfo>, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => list_peers(&conn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+
+    let peers = match db_result {
+        Ok(peers) => peers,
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            return Ok(res);
+        }
+    };
+    let export = PeerExport {
+        peers: peers
+            .into_iter()
+            .map(|info| {
+                let state = info.state;
+                ExportedPeer {
+                    peer: Peer::from(info),
+                    state,
+                }
+            })
+            .collect(),
+    };
+
+    let mut res = Response::new(StatusCode::Ok);
+    if is_toml_format(&req) {
+        res.insert_header("Content-Type", "application/toml; charset=utf-8");
+        res.set_body(toml::to_string(&export)?);
+    } else {
+        res.insert_header("Content-Type", "application/json; charset=utf-8");
+        res.set_body(serde_json::to_string_pretty(&export)?);
+    }
+    Ok(res)
+}
+
+fn import_result(asn: u64, outcome: &str, message: Option<String>) -> ImportResult {
+    ImportResult {
+        asn,
+        outcome: outcome.to_string(),
+        message,
+    }
+}
+
+// 写入 disabled 状态的配置：BIRD 配置带有 disabled 标记，不启动隧道也不设置开机启动
+async fn write_disabled_config(
+    peer: Peer,
+    paths: ConfigPaths,
+    reload: BirdReload,
+) -> Result<(), String> {
+    smol::unblock(move || {
+        let wg_config = gen_wireguard_config(&peer)
+            .map_err(|e| format!("Failed to generate WireGuard config: {}", e))?;
+        let bird_config = gen_bird_config(&peer, PeerState::Disabled)
+            .map_err(|e| format!("Failed to generate BIRD config: {}", e))?;
+        save_config(&paths.wireguard, &wg_config, &paths.bird, &bird_config)
+            .map_err(|e| format!("Failed to save config: {}", e))?;
+        reload
+            .request()
+            .map_err(|e| format!("Failed to apply config: {}", e))
+    })
+    .await
+}
+
+// 导出时为 disabled 的 peer 直接以 disabled 写入，会话在导入期间不会建立
+// 这些 peer 已在导出端通过审批，不再进入 pending
+async fn import_disabled_peer(
+    mut peer: Peer,
+    db: Db,
+    dry_run: bool,
+    reload: BirdReload,
+) -> ImportResult {
+    let asn = peer.asn;
+    let failed = |message: String| import_result(asn, "failed", Some(message));
+    if let Err(msg) = peer.validate() {
+        return failed(msg);
+    }
+
+    let existing = match fetch_peer(asn, db.clone()).await {
+        Ok(existing) => existing.filter(|info| info.state != PeerState::Rejected),
+        Err(e) => return failed(e.message),
+    };
+    if let Some(info) = existing {
+        // 与 /add 相同：完全相同的重复导入视为成功，并重新写入配置
+        let paths = ConfigPaths::from(&info);
+        if info.state != PeerState::Disabled || Peer::from(info) != peer {
+            return failed(format!("Peer already exists: {}", asn));
+        }
+        if !dry_run && let Err(msg) = write_disabled_config(peer, paths, reload).await {
+            return failed(msg);
+        }
+        return import_result(asn, "unchanged", None);
+    }
+    if dry_run {
+        return import_result(
+            asn,
+            "would_add",
+            Some("Would be imported as disabled".to_string()),
+        );
+    }
+
+    let peer_clone = peer.clone();
+    let db_clone = db.clone();
+    let db_result: Result<usize, PeerDbError> = smol::unblock(move || match db_clone.lock() {
+        Ok(conn) => add_peer(&conn, &peer_clone, PeerState::Disabled),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    match db_result {
+        Ok(_) => {}
+        Err(PeerDbError::AlreadyExist) => return failed(format!("Peer already exists: {}", asn)),
+        Err(e) => return failed(db_error(e).message),
+    }
+    let result = match stored_paths(asn, db).await {
+        Ok(paths) => write_disabled_config(peer, paths, reload).await,
+        Err(msg) => Err(msg),
+    };
+    if let Err(msg) = result {
+        return failed(msg);
+    }
+    println!("Peer imported as disabled: {}", asn);
+    import_result(asn, "disabled", None)
+}
+
+// 每个条目都经过与 /add 相同的校验与部署流程，导出时为 disabled 的 peer 以 disabled 状态导入
+// 各条目只写入配置并启动隧道，全部处理完后只执行一次 birdc configure
+pub async fn import_peers(export: PeerExport, db: Db, dry_run: bool) -> ImportReport {
+    let mut results = Vec::new();
+    let mut reloads = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    for entry in export.peers {
+        let asn = entry.peer.asn;
+        if entry.state == PeerState::Rejected {
+            results.push(ImportResult {
+                asn,
+                outcome: "skipped".to_string(),
+                message: Some("Peer was rejected".to_string()),
+            });
+            continue;
+        }
+        if !seen.insert(asn) {
+            results.push(ImportResult {
+                asn,
+                outcome: "failed".to_string(),
+                message: Some(format!("Duplicate entry: {}", asn)),
+            });
+            continue;
+        }
+
+        let reload = BirdReload::deferred();
+        if entry.state == PeerState::Disabled {
+            results
+                .push(import_disabled_peer(entry.peer, db.clone(), dry_run, reload.clone()).await);
+            reloads.push((results.len() - 1, reload));
+            continue;
+        }
+
+        let result = match add_peer_flow(entry.peer, db.clone(), dry_run, reload.clone()).await {
+            Ok(AddOutcome::WouldAdd(_)) => ImportResult {
+                asn,
+                outcome: "would_add".to_string(),
+                message: None,
+            },
+            Ok(AddOutcome::Pending) => ImportResult {
+                asn,
+                outcome: "pending".to_string(),
+                message: None,
+            },
+            Ok(AddOutcome::Added) => ImportResult {
+                asn,
+                outcome: "added".to_string(),
+                message: None,
+            },
+            Ok(AddOutcome::Unchanged) => ImportResult {
+                asn,
+                outcome: "unchanged".to_string(),
+                message: None,
+            },
+            Err(e) => ImportResult {
+                asn,
+                outcome: "failed".to_string(),
+                message: Some(e.message),
+            },
+        };
+        results.push(result);
+        reloads.push((results.len() - 1, reload));
+    }
+
+    let pending: Vec<usize> = reloads
+        .iter()
+        .filter(|(_, reload)| reload.is_pending())
+        .map(|(index, _)| *index)
+        .collect();
+    if !pending.is_empty()
+        && let Err(e) = smol::unblock(reconfigure_bird).await
+    {
+        for index in pending {
+            let result = &mut results[index];
+            if result.outcome != "failed" {
+                result.outcome = "failed".to_string();
+                result.message = Some(format!("Failed to apply config: {}", e));
+            }
+        }
+    }
+
+    let failed = results.iter().filter(|r| r.outcome == "failed").count();
+    ImportReport {
+        dry_run,
+        succeeded: results.len() - failed,
+        failed,
+        results,
+    }
+}
+
+pub async fn handle_import(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let dry_run = is_dry_run(&req);
+    let toml_format = is_toml_format(&req);
+    let body = req.body_string().await?;
+    let parsed = if toml_format {
+        toml::from_str::<PeerExport>(&body).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_str::<PeerExport>(&body).map_err(|e| e.to_string())
+    };
+    let export = match parsed {
+        Ok(export) => export,
+        Err(e) => {
+            eprintln!("Failed to parse import document: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid import document: {}", e));
+            return Ok(res);
+        }
+    };
+
+    let report = import_peers(export, db, dry_run).await;
+    println!(
+        "Import finished{}: {} succeeded, {} failed",
+        if dry_run { " (dry run)" } else { "" },
+        report.succeeded,
+        report.failed
+    );
+    let mut res = Response::new(StatusCode::Ok);
+    res.insert_header("Content-Type", "application/json; charset=utf-8");
+    res.set_body(serde_json::to_string(&report)?);
+    Ok(res)
+}
+
+pub async fn handle_adopt(req: Request, db: Db) -> http_types::Result<Response> {
+    let config = config();
+    let dry_run = is_dry_run(&req);
+
+    let adopt_result: Result<AdoptReport, String> = smol::unblock(move || match db.lock() {
+        Ok(conn) => adopt_existing_peers(
+            &conn,
+            &config.paths.wireguard_dir,
+            &config.paths.bird_peers_dir,
+            dry_run,
+        ),
+        Err(e) => Err(e.to_string()),
+    })
+    .await;
+
+    match adopt_result {
+        Ok(report) => {
+            println!(
+                "Adopt finished{}: {} entries",
+                if dry_run { " (dry run)" } else { "" },
+                report.results.len()
+            );
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header("Content-Type", "application/json; charset=utf-8");
+            res.set_body(serde_json::to_string(&report)?);
+            Ok(res)
+        }
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Failed to adopt existing peers: {}", e));
+            Ok(res)
+        }
+    }
+}
+
+// 重新生成所有已部署 peer 的 WireGuard 与 BIRD 配置，仅重启 WireGuard 配置发生变化的隧道。
+// full 为 true 时同时确保隧道状态与数据库一致
+pub async fn resync_peers(db: Db, full: bool) -> Result<Vec<ResyncResult>, OpError> {
+    let db_result: Result<Vec<PeerDbInfo>, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => list_peers(&conn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+
+    let peers = db_result.map_err(|e| {
+        OpError::new(
+            StatusCode::InternalServerError,
+            format!("Database error: {}", e),
+        )
+    })?;
+
+    let results = smol::unblock(move || {
+        let mut bird_changed = false;
+        let mut results: Vec<ResyncResult> = peers
+            .into_iter()
+            .filter(|info| info.state.is_deployed())
+            .map(|info| {
+                let interface_name = info.interface_name.clone();
+                let wg_config_path = info.wireguard_config_path.clone();
+                let bird_config_path = info.bird_config_path.clone();
+                let state = info.state;
+                let peer = Peer::from(info);
+                let outcome = gen_wireguard_config(&peer).and_then(|wg_config| {
+                    let mut changed = update_config_file(&wg_config_path, &wg_config)?;
+                    if changed && state == PeerState::Active {
+                        restart_tunnel(&interface_name)?;
+                    }
+                    // BIRD 配置中的 source address 来自 Peer.link_local
+                    let bird_config = gen_bird_config(&peer, state)?;
+                    if update_config_file(&bird_config_path, &bird_config)? {
+                        bird_changed = true;
+                        changed = true;
+                    }
+                    if full {
+                        if state == PeerState::Active {
+                            start_tunnel(&interface_name)?;
+                        } else {
+                            enforce_disabled(&interface_name)?;
+                        }
+                    }
+                    Ok(changed)
+                });
+                match outcome {
+                    Ok(changed) => ResyncResult {
+                        asn: peer.asn,
+                        interface_name,
+                        changed,
+                        error: None,
+                    },
+                    Err(e) => ResyncResult {
+                        asn: peer.asn,
+                        interface_name,
+                        changed: false,
+                        error: Some(e.to_string()),
+                    },
+                }
+            })
+            .collect();
+
+        // 所有 BIRD 配置写入后只重新加载一次
+        if bird_changed && let Err(e) = reconfigure_bird() {
+            for result in results.iter_mut().filter(|r| r.changed) {
+                result.error = Some(e.to_string());
+            }
+        }
+        results
+    })
+    .await;
+
+    println!(
+        "Resync finished: {} peers, {} changed, {} failed",
+        results.len(),
+        results.iter().filter(|r| r.changed).count(),
+        results.iter().filter(|r| r.error.is_some()).count()
+    );
+    Ok(results)
+}
+
+pub async fn handle_resync(db: Db) -> http_types::Result<Response> {
+    let results = match resync_peers(db, false).await {
+        Ok(results) => results,
+        Err(e) => return Ok(e.into_response()),
+    };
+
+    let status = if results.iter().all(|r| r.error.is_none()) {
+        StatusCode::Ok
+    } else {
+        StatusCode::InternalServerError
+    };
+    let mut res = Response::new(status);
+    res.insert_header("Content-Type", "application/json; charset=utf-8");
+    res.set_body(serde_json::to_string(&results)?);
+    Ok(res)
+}
+
+pub async fn handle_audit(mut req: Request, db: Db) -> http_types::Result<Response> {
+    let query: AuditQuery = match req.body_json().await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {}", e);
+            let mut res = Response::new(StatusCode::BadRequest);
+            res.set_body(format!("Invalid JSON: {}", e));
+            return Ok(res);
+        }
+    };
+
+    let db_result: Result<Vec<AuditEntry>, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => query_audit(&conn, &query),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+
+    match db_result {
+        Ok(entries) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header("Content-Type", "application/json; charset=utf-8");
+            res.set_body(serde_json::to_string(&entries)?);
+            Ok(res)
+        }
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            Ok(res)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Harness, TEST_PRIVATE_KEY, TEST_SECRET};
+
+    const ASN: u64 = 4242420253;
+    const WG_PATH: &str = "/etc/wireguard/dn42_0253.conf";
+    const BIRD_PATH: &str = "/etc/bird/peers/dn42_0253.conf";
+    const DEPLOY: [&str; 3] = [
+        "/usr/bin/systemctl start wg-quick@dn42_0253",
+        "/usr/bin/systemctl enable wg-quick@dn42_0253",
+        "/usr/sbin/birdc configure",
+    ];
+    const REMOVE: [&str; 3] = [
+        "/usr/bin/systemctl disable wg-quick@dn42_0253",
+        "/usr/bin/systemctl stop wg-quick@dn42_0253",
+        "/usr/sbin/birdc configure",
+    ];
+
+    fn peer_body() -> String {
+        serde_json::json!({
+            "asn": ASN,
+            "wireguard_endpoint": "peer.example.net:51820",
+            "wireguard_link_local": "fe80::1",
+            "wireguard_public_key": "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=",
+        })
+        .to_string()
+    }
+
+    fn asn_body() -> String {
+        serde_json::json!({ "asn": ASN }).to_string()
+    }
+
+    async fn send(h: &Harness, path: &str, body: String) -> (StatusCode, String) {
+        let mut req = Request::new(Method::Post, format!("http://localhost{}", path).as_str());
+        req.insert_header("Authorization", format!("Bearer {}", TEST_SECRET));
+        req.set_body(body);
+        let mut res = serve_router(req, h.db.clone()).await.unwrap();
+        (res.status(), res.body_string().await.unwrap())
+    }
+
+    #[test]
+    fn test_add_get_del() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let mut req = Request::new(Method::Post, "http://localhost/add");
+            req.set_body(peer_body());
+            let res = serve_router(req, h.db.clone()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::Unauthorized);
+            assert!(h.backend.commands().is_empty());
+
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(h.backend.commands(), DEPLOY);
+            let wg_config = h.backend.file(WG_PATH).unwrap();
+            assert!(wg_config.contains(&format!("PrivateKey = {}", TEST_PRIVATE_KEY)));
+            assert!(wg_config.contains("ListenPort = 20253"));
+            assert!(wg_config.contains("Endpoint = peer.example.net:51820"));
+            let bird_config = h.backend.file(BIRD_PATH).unwrap();
+            assert!(bird_config.contains("neighbor fe80::1 % 'dn42_0253' as 4242420253;"));
+
+            let (status, body) = send(&h, "/get", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok);
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "active");
+            assert_eq!(info["wireguard_config_path"], WG_PATH);
+
+            // 相同内容的重复请求重新部署，不同内容仍然冲突
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Ok);
+            assert_eq!(body, format!("Peer already added: {}", ASN));
+            assert_eq!(h.backend.commands(), DEPLOY);
+            let changed = peer_body().replace("51820", "51821");
+            let (status, _) = send(&h, "/add?wait=true", changed).await;
+            assert_eq!(status, StatusCode::Conflict);
+
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/del?wait=true", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(h.backend.commands(), REMOVE);
+            assert!(h.backend.file(WG_PATH).is_none());
+            assert!(h.backend.file(BIRD_PATH).is_none());
+
+            let (status, body) = send(&h, "/get", asn_body()).await;
+            assert_eq!(status, StatusCode::BadRequest);
+            assert_eq!(body, format!("Peer not found: {}", ASN));
+        });
+    }
+
+    #[test]
+    fn test_update_and_dry_run() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let (status, _) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Ok);
+            let wg_before = h.backend.file(WG_PATH).unwrap();
+            h.backend.clear_commands();
+
+            let update = serde_json::json!({ "asn": ASN, "mtu": 1400 }).to_string();
+            let (status, body) = send(&h, "/update?dry_run=true", update.clone()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let plan: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(plan["files"][0]["action"], "write");
+            assert_eq!(plan["files"][1]["action"], "unchanged");
+            assert_eq!(
+                plan["commands"],
+                serde_json::json!(["/usr/bin/systemctl restart wg-quick@dn42_0253"])
+            );
+            assert!(!body.contains(TEST_PRIVATE_KEY));
+            assert!(h.backend.commands().is_empty());
+            assert_eq!(h.backend.file(WG_PATH).unwrap(), wg_before);
+
+            // 只有 WireGuard 配置变化，BIRD 不需要重新加载
+            let (status, _) = send(&h, "/update?wait=true", update).await;
+            assert_eq!(status, StatusCode::Ok);
+            assert_eq!(
+                h.backend.commands(),
+                ["/usr/bin/systemctl restart wg-quick@dn42_0253"]
+            );
+            assert!(h.backend.file(WG_PATH).unwrap().contains("MTU = 1400"));
+
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/del?dry_run=true", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok);
+            let plan: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(plan["commands"], serde_json::json!(REMOVE));
+            assert!(h.backend.commands().is_empty());
+            assert!(h.backend.file(WG_PATH).is_some());
+        });
+    }
+
+    #[test]
+    fn test_import_disabled_peer() {
+        // 开启审批时 disabled 的条目也不会变为 pending
+        let h = Harness::with_config("[Approval]\nrequire_approval = true");
+        smol::block_on(async {
+            let mut entry: serde_json::Value = serde_json::from_str(&peer_body()).unwrap();
+            entry["state"] = "disabled".into();
+            let export = serde_json::json!({ "peers": [entry] }).to_string();
+
+            let (status, body) = send(&h, "/import?dry_run=true", export.clone()).await;
+            assert_eq!(status, StatusCode::Ok);
+            let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(report["results"][0]["outcome"], "would_add");
+            assert!(h.backend.commands().is_empty());
+
+            // 只写入配置并重新加载 BIRD，不启动隧道
+            let (status, body) = send(&h, "/import", export.clone()).await;
+            assert_eq!(status, StatusCode::Ok);
+            let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(report["results"][0]["outcome"], "disabled", "{}", body);
+            assert_eq!(h.backend.commands(), ["/usr/sbin/birdc configure"]);
+            assert!(h.backend.file(WG_PATH).is_some());
+            assert!(h.backend.file(BIRD_PATH).unwrap().contains("disabled;"));
+            let (_, body) = send(&h, "/get", asn_body()).await;
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "disabled");
+
+            h.backend.clear_commands();
+            let (_, body) = send(&h, "/import", export).await;
+            let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(report["results"][0]["outcome"], "unchanged");
+            assert_eq!(h.backend.commands(), ["/usr/sbin/birdc configure"]);
+
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/enable", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(
+                h.backend.commands(),
+                [
+                    "/usr/bin/systemctl start wg-quick@dn42_0253",
+                    "/usr/bin/systemctl enable wg-quick@dn42_0253",
+                    "/usr/sbin/birdc enable dn42_0253",
+                ]
+            );
+            assert!(!h.backend.file(BIRD_PATH).unwrap().contains("disabled;"));
+        });
+    }
+
+    #[test]
+    fn test_disable_and_enable() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            h.backend.clear_commands();
+
+            let (status, body) = send(&h, "/disable", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(
+                h.backend.commands(),
+                [
+                    "/usr/bin/systemctl stop wg-quick@dn42_0253",
+                    "/usr/bin/systemctl disable wg-quick@dn42_0253",
+                    "/usr/sbin/birdc disable dn42_0253",
+                ]
+            );
+            assert!(h.backend.file(BIRD_PATH).unwrap().contains("disabled;"));
+            assert!(h.backend.file(WG_PATH).is_some());
+            let (status, body) = send(&h, "/get", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok);
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "disabled");
+
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/disable", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok);
+            assert_eq!(body, format!("Peer already disabled: {}", ASN));
+            assert!(h.backend.commands().is_empty());
+
+            // 启动时重新关闭已禁用的 peer
+            crate::enforce_disabled_peers(&h.db.lock().unwrap());
+            assert_eq!(
+                h.backend.commands(),
+                [
+                    "/usr/bin/systemctl stop wg-quick@dn42_0253",
+                    "/usr/sbin/birdc disable dn42_0253",
+                ]
+            );
+
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/enable", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(
+                h.backend.commands(),
+                [
+                    "/usr/bin/systemctl start wg-quick@dn42_0253",
+                    "/usr/bin/systemctl enable wg-quick@dn42_0253",
+                    "/usr/sbin/birdc enable dn42_0253",
+                ]
+            );
+            assert!(!h.backend.file(BIRD_PATH).unwrap().contains("disabled;"));
+            let (_, body) = send(&h, "/get", asn_body()).await;
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "active");
+
+            h.backend.clear_commands();
+            crate::enforce_disabled_peers(&h.db.lock().unwrap());
+            assert!(h.backend.commands().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_add_is_queued_by_default() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let (status, body) = send(&h, "/add", peer_body()).await;
+            assert_eq!(status, StatusCode::Accepted, "{}", body);
+            let accepted: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(accepted["status"], "queued");
+            assert!(h.backend.commands().is_empty());
+
+            // 无效的请求在入队前拒绝
+            let (status, _) = send(&h, "/add", r#"{"asn": 1}"#.to_string()).await;
+            assert_eq!(status, StatusCode::BadRequest);
+        });
+    }
+
+    #[test]
+    fn test_queued_del_and_update_are_validated() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            // 不存在的 peer 与无效的修改和同步路径返回相同的错误，不进入队列
+            let (status, body) = send(&h, "/del", asn_body()).await;
+            assert_eq!(status, StatusCode::BadRequest);
+            assert_eq!(body, format!("Peer not found: {}", ASN));
+            let (status, body) = send(&h, "/update", asn_body()).await;
+            assert_eq!(status, StatusCode::BadRequest);
+            assert_eq!(body, format!("Peer not found: {}", ASN));
+
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let invalid = format!(r#"{{"asn": {}, "mtu": 100}}"#, ASN);
+            let (status, body) = send(&h, "/update", invalid.clone()).await;
+            assert_eq!(status, StatusCode::BadRequest);
+            assert!(body.starts_with("Invalid MTU"), "{}", body);
+
+            let mut req = Request::new(
+                Method::Patch,
+                format!("http://localhost/v1/peers/{}", ASN).as_str(),
+            );
+            req.insert_header("Authorization", format!("Bearer {}", TEST_SECRET));
+            req.set_body(invalid);
+            let res = serve_router(req, h.db.clone()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BadRequest);
+
+            let conn = h.db.lock().unwrap();
+            assert!(claim_next_job(&conn).unwrap().is_none());
+        });
+    }
+
+    async fn send_with_key(h: &Harness, path: &str, key: &str, body: String) -> Response {
+        let mut req = Request::new(Method::Post, format!("http://localhost{}", path).as_str());
+        req.insert_header("Authorization", format!("Bearer {}", TEST_SECRET));
+        req.insert_header("Idempotency-Key", key);
+        req.set_body(body);
+        serve_router(req, h.db.clone()).await.unwrap()
+    }
+
+    #[test]
+    fn test_idempotent_add_is_replayed() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let mut first = send_with_key(&h, "/add", "retry-1", peer_body()).await;
+            assert_eq!(first.status(), StatusCode::Accepted);
+            let location = first.header("Location").unwrap().as_str().to_string();
+            let body = first.body_string().await.unwrap();
+
+            // 相同的请求返回保存的结果，包括 Location，不会再次入队
+            let mut replayed = send_with_key(&h, "/add", "retry-1", peer_body()).await;
+            assert_eq!(replayed.status(), StatusCode::Accepted);
+            assert_eq!(replayed.header("Location").unwrap().as_str(), location);
+            assert_eq!(
+                replayed.header("Idempotent-Replayed").unwrap().as_str(),
+                "true"
+            );
+            assert_eq!(replayed.body_string().await.unwrap(), body);
+            {
+                let conn = h.db.lock().unwrap();
+                assert!(claim_next_job(&conn).unwrap().is_some());
+                assert!(claim_next_job(&conn).unwrap().is_none());
+            }
+
+            let res = send_with_key(&h, "/add", "retry-1", asn_body()).await;
+            assert_eq!(res.status(), StatusCode::UnprocessableEntity);
+        });
+    }
+
+    #[test]
+    fn test_identical_readd_returns_ok() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let mut res = send_with_key(&h, "/add?wait=true", "first", peer_body()).await;
+            assert_eq!(res.status(), StatusCode::Ok);
+            assert_eq!(
+                res.body_string().await.unwrap(),
+                format!("Peer added: {}", ASN)
+            );
+            h.backend.clear_commands();
+
+            // 新的 key 重复提交相同的 peer，重新部署并返回 200 而不是 409
+            let mut res = send_with_key(&h, "/add?wait=true", "second", peer_body()).await;
+            assert_eq!(res.status(), StatusCode::Ok);
+            assert_eq!(
+                res.body_string().await.unwrap(),
+                format!("Peer already added: {}", ASN)
+            );
+            assert!(res.header("Idempotent-Replayed").is_none());
+            assert_eq!(h.backend.commands(), DEPLOY);
+
+            let mut changed: serde_json::Value = serde_json::from_str(&peer_body()).unwrap();
+            changed["wireguard_endpoint"] = "other.example.net:51820".into();
+            let res = send_with_key(&h, "/add?wait=true", "third", changed.to_string()).await;
+            assert_eq!(res.status(), StatusCode::Conflict);
+        });
+    }
+
+    #[test]
+    fn test_manual_approval() {
+        let h = Harness::with_config("[Approval]\nrequire_approval = true");
+        smol::block_on(async {
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Accepted);
+            assert_eq!(body, format!("Peer pending approval: {}", ASN));
+            assert!(h.backend.commands().is_empty());
+            assert!(h.backend.file(WG_PATH).is_none());
+            let (_, body) = send(&h, "/get", asn_body()).await;
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "pending");
+
+            let (status, body) = send(&h, "/approve", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(h.backend.commands(), DEPLOY);
+            assert!(h.backend.file(WG_PATH).is_some());
+            assert!(h.backend.file(BIRD_PATH).is_some());
+            let (status, _) = send(&h, "/approve", asn_body()).await;
+            assert_eq!(status, StatusCode::Conflict);
+
+            // 被拒绝的 peer 保留原因，之后可以重新申请
+            h.backend.clear_commands();
+            let mut other: serde_json::Value = serde_json::from_str(&peer_body()).unwrap();
+            other["asn"] = 4242420254u64.into();
+            let other_asn = r#"{"asn": 4242420254}"#.to_string();
+            let (status, _) = send(&h, "/add?wait=true", other.to_string()).await;
+            assert_eq!(status, StatusCode::Accepted);
+            let reject = r#"{"asn": 4242420254, "reason": "not in registry"}"#;
+            let (status, body) = send(&h, "/reject", reject.to_string()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let (_, body) = send(&h, "/get", other_asn.clone()).await;
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "rejected");
+            assert_eq!(info["reject_reason"], "not in registry");
+            let (status, _) = send(&h, "/approve", other_asn.clone()).await;
+            assert_eq!(status, StatusCode::Conflict);
+
+            other["wireguard_endpoint"] = "other.example.net:51820".into();
+            let (status, _) = send(&h, "/add?wait=true", other.to_string()).await;
+            assert_eq!(status, StatusCode::Accepted);
+            let (_, body) = send(&h, "/get", other_asn).await;
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["state"], "pending");
+            assert_eq!(info["wireguard_endpoint"], "other.example.net:51820");
+            assert!(info.get("reject_reason").is_none());
+            assert!(h.backend.commands().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_auto_approval() {
+        let h = Harness::with_config(
+            "[Approval]\nrequire_approval = true\nauto_approve = true\n\
+             check_endpoint_reachable = true\nping_path = \"/bin/ping\"",
+        );
+        smol::block_on(async {
+            let mut body: serde_json::Value = serde_json::from_str(&peer_body()).unwrap();
+            body["wireguard_endpoint"] = "192.0.2.1:51820".into();
+            let (status, res) = send(&h, "/add?wait=true", body.to_string()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", res);
+            let mut expected = vec!["/bin/ping -c 1 -W 2 192.0.2.1"];
+            expected.extend(DEPLOY);
+            assert_eq!(h.backend.commands(), expected);
+
+            // 无法 ping 通的 endpoint 留待人工审批
+            h.backend.clear_commands();
+            h.backend.fail_on("/bin/ping", "100% packet loss");
+            body["asn"] = 4242420254u64.into();
+            body["wireguard_endpoint"] = "192.0.2.2:51820".into();
+            let (status, res) = send(&h, "/add?wait=true", body.to_string()).await;
+            assert_eq!(status, StatusCode::Accepted, "{}", res);
+            assert_eq!(h.backend.commands(), ["/bin/ping -c 1 -W 2 192.0.2.2"]);
+            assert!(h.backend.file("/etc/wireguard/dn42_0254.conf").is_none());
+        });
+    }
+
+    #[test]
+    fn test_deploy_uses_stored_paths() {
+        let h = Harness::with_config("[Approval]\nrequire_approval = true");
+        smol::block_on(async {
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Accepted, "{}", body);
+
+            // 重新加载后 Paths 指向新目录，已有 peer 仍使用数据库中记录的路径
+            h.reload_config(|config| {
+                config.paths.wireguard_dir = "/etc/wireguard-new".to_string();
+                config.paths.bird_peers_dir = "/etc/bird/peers-new".to_string();
+            });
+            let new_files = [
+                "/etc/wireguard-new/dn42_0253.conf",
+                "/etc/bird/peers-new/dn42_0253.conf",
+            ];
+
+            let (status, body) = send(&h, "/approve", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert!(h.backend.file(WG_PATH).is_some());
+            assert!(h.backend.file(BIRD_PATH).is_some());
+            assert!(new_files.iter().all(|path| h.backend.file(path).is_none()));
+
+            let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert!(new_files.iter().all(|path| h.backend.file(path).is_none()));
+
+            let (status, body) = send(&h, "/del?wait=true", asn_body()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert!(h.backend.file(WG_PATH).is_none());
+            assert!(h.backend.file(BIRD_PATH).is_none());
+        });
+    }
+
+    #[test]
+    fn test_import_reconfigures_once() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let peers: Vec<serde_json::Value> = (1..=3u64)
+                .map(|i| {
+                    let mut entry: serde_json::Value = serde_json::from_str(&peer_body()).unwrap();
+                    entry["asn"] = (ASN + i).into();
+                    entry["wireguard_link_local"] = format!("fe80::{}", i + 1).into();
+                    entry
+                })
+                .collect();
+            let export = serde_json::json!({ "peers": peers }).to_string();
+
+            let (status, body) = send(&h, "/import", export).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+            for result in report["results"].as_array().unwrap() {
+                assert_eq!(result["outcome"], "added", "{}", body);
+            }
+            let commands = h.backend.commands();
+            let configures = commands
+                .iter()
+                .filter(|command| *command == "/usr/sbin/birdc configure")
+                .count();
+            assert_eq!(configures, 1, "{:?}", commands);
+            assert_eq!(commands.last().unwrap(), "/usr/sbin/birdc configure");
+            assert_eq!(commands.len(), 3 * 2 + 1, "{:?}", commands);
+        });
+    }
+
+    #[test]
+    fn test_import_reload_failure() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let export = serde_json::json!({
+                "peers": [serde_json::from_str::<serde_json::Value>(&peer_body()).unwrap()]
+            })
+            .to_string();
+            h.backend
+                .fail_on("/usr/sbin/birdc configure", "injected failure");
+            let (_, body) = send(&h, "/import", export).await;
+            let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(report["results"][0]["outcome"], "failed", "{}", body);
+            assert!(
+                report["results"][0]["message"]
+                    .as_str()
+                    .unwrap()
+                    .contains("injected failure"),
+                "{}",
+                body
+            );
+        });
+    }
+
+    #[test]
+    fn test_resync_after_config_change() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let mut other: serde_json::Value = serde_json::from_str(&peer_body()).unwrap();
+            other["asn"] = 4242420254u64.into();
+            other["wireguard_link_local"] = "fe80::2".into();
+            for body in [peer_body(), other.to_string()] {
+                let (status, body) = send(&h, "/add?wait=true", body).await;
+                assert_eq!(status, StatusCode::Ok, "{}", body);
+            }
+            h.backend.clear_commands();
+
+            h.reload_config(|config| {
+                config.peer.allowed_ips.push("fd10::/8".to_string());
+                config.peer.link_local = "fe80::254".to_string();
+            });
+            let (status, body) = send(&h, "/resync", String::new()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert!(
+                results
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .all(|r| r["changed"] == true)
+            );
+            for (wg_path, bird_path) in [
+                (WG_PATH, BIRD_PATH),
+                (
+                    "/etc/wireguard/dn42_0254.conf",
+                    "/etc/bird/peers/dn42_0254.conf",
+                ),
+            ] {
+                assert!(h.backend.file(wg_path).unwrap().contains("fd10::/8"));
+                assert!(
+                    h.backend
+                        .file(bird_path)
+                        .unwrap()
+                        .contains("source address fe80::254;")
+                );
+            }
+            // 两个 peer 的 BIRD 配置都变化了，只执行一次 birdc configure
+            assert_eq!(
+                h.backend.commands(),
+                [
+                    "/usr/bin/systemctl restart wg-quick@dn42_0253",
+                    "/usr/bin/systemctl restart wg-quick@dn42_0254",
+                    "/usr/sbin/birdc configure",
+                ]
+            );
+
+            // 配置未变化的 peer 不会重启
+            h.backend.clear_commands();
+            let (status, body) = send(&h, "/resync", String::new()).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(results.as_array().unwrap().len(), 2);
+            assert!(
+                results
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .all(|r| r["changed"] == false)
+            );
+            assert!(h.backend.commands().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_add_command_failures() {
+        for (index, command) in DEPLOY.iter().enumerate() {
+            let h = Harness::setup();
+            smol::block_on(async {
+                h.backend.fail_on(command, "injected failure");
+                let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+                assert_eq!(status, StatusCode::InternalServerError, "{}", command);
+                assert!(body.contains("injected failure"), "{}", body);
+                // 失败的命令之后不再执行其他命令
+                assert_eq!(h.backend.commands(), DEPLOY[..=index]);
+
+                // 重试时重新部署已写入数据库的 peer
+                h.backend.clear_failures();
+                h.backend.clear_commands();
+                let (status, body) = send(&h, "/add?wait=true", peer_body()).await;
+                assert_eq!(status, StatusCode::Ok, "{}", body);
+                assert_eq!(h.backend.commands(), DEPLOY);
+            });
+        }
+    }
+
+    #[test]
+    fn test_del_command_failures() {
+        for (index, command) in REMOVE.iter().enumerate() {
+            let h = Harness::setup();
+            smol::block_on(async {
+                let (status, _) = send(&h, "/add?wait=true", peer_body()).await;
+                assert_eq!(status, StatusCode::Ok);
+                h.backend.clear_commands();
+
+                h.backend.fail_on(command, "injected failure");
+                let (status, body) = send(&h, "/del?wait=true", asn_body()).await;
+                assert_eq!(status, StatusCode::InternalServerError, "{}", command);
+                assert!(body.contains("injected failure"), "{}", body);
+                assert_eq!(h.backend.commands(), REMOVE[..=index]);
+                let (status, _) = send(&h, "/get", asn_body()).await;
+                assert_eq!(status, StatusCode::Ok);
+
+                h.backend.clear_failures();
+                let (status, body) = send(&h, "/del?wait=true", asn_body()).await;
+                assert_eq!(status, StatusCode::Ok, "{}", body);
+                let (status, _) = send(&h, "/get", asn_body()).await;
+                assert_eq!(status, StatusCode::BadRequest);
+            });
+        }
     }
 }
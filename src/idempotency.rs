@@ -0,0 +1,246 @@
+use crate::Db;
+use crate::audit::request_principal;
+use crate::db::*;
+use crate::model::*;
+use http_types::{Method, Request, Response, StatusCode};
+use std::future::Future;
+
+// 保存的结果在 24 小时后过期，之后同一个 key 可以再次使用
+const KEY_TTL_SECS: i64 = 86_400;
+const MAX_KEY_LENGTH: usize = 255;
+
+// GET 与 HEAD 本身可以安全重试，不需要 Idempotency-Key
+pub fn idempotency_key(req: &Request) -> Option<String> {
+    if matches!(req.method(), Method::Get | Method::Head) {
+        return None;
+    }
+    req.header("Idempotency-Key")
+        .map(|values| values.as_str().trim().to_string())
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LENGTH && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// 同一个 key 只能用于方法、路径与请求体都相同的请求
+fn fingerprint(req: &Request, body: &[u8]) -> String {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(req.method().as_ref().as_bytes());
+    ctx.update(b" ");
+    ctx.update(req.url().path().as_bytes());
+    if let Some(query) = req.url().query() {
+        ctx.update(b"?");
+        ctx.update(query.as_bytes());
+    }
+    ctx.update(b"\n");
+    ctx.update(body);
+    ctx.finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn text_response(status: StatusCode, body: &str) -> Response {
+    let mut res = Response::new(status);
+    res.insert_header("Content-Type", "text/plain; charset=utf-8");
+    res.set_body(format!("{}\n", body));
+    res
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let status = StatusCode::try_from(stored.status).unwrap_or(StatusCode::Ok);
+    let mut res = Response::new(status);
+    res.insert_header("Idempotent-Replayed", "true");
+    if let Some(content_type) = stored.content_type {
+        res.insert_header("Content-Type", content_type);
+    }
+    if let Some(location) = stored.location {
+        res.insert_header("Location", location);
+    }
+    res.set_body(stored.body);
+    res
+}
+
+// 按 Idempotency-Key 执行请求：首次执行并保存结果，之后相同的请求直接返回保存的结果
+// 服务端错误不保存，客户端可以用同一个 key 重试
+pub async fn idempotent<F, Fut>(
+    key: String,
+    mut req: Request,
+    db: Db,
+    handler: F,
+) -> http_types::Result<Response>
+where
+    F: FnOnce(Request, Db) -> Fut,
+    Fut: Future<Output = http_types::Result<Response>>,
+{
+    if !is_valid_key(&key) {
+        return Ok(text_response(
+            StatusCode::BadRequest,
+            &format!(
+                "Invalid Idempotency-Key: must be 1 to {} printable ASCII characters",
+                MAX_KEY_LENGTH
+            ),
+        ));
+    }
+    let body = req.body_bytes().await?;
+    let fingerprint = fingerprint(&req, &body);
+    req.set_body(body);
+    let principal = request_principal(&req);
+
+    let claim = {
+        let (db, principal, key) = (db.clone(), principal.clone(), key.clone());
+        smol::unblock(move || match db.lock() {
+            Ok(conn) => claim_idempotency_key(&conn, &principal, &key, &fingerprint, KEY_TTL_SECS),
+            Err(e) => Err(PeerDbError::LockError(e.to_string())),
+        })
+        .await
+    };
+    match claim {
+        Ok(IdempotencyClaim::Claimed) => {}
+        Ok(IdempotencyClaim::InProgress) => {
+            let mut res = text_response(
+                StatusCode::Conflict,
+                "A request with this Idempotency-Key is still in progress",
+            );
+            res.insert_header("Retry-After", "1");
+            return Ok(res);
+        }
+        Ok(IdempotencyClaim::Mismatch) => {
+            return Ok(text_response(
+                StatusCode::UnprocessableEntity,
+                "Idempotency-Key was already used for a different request",
+            ));
+        }
+        Ok(IdempotencyClaim::Completed(stored)) => return Ok(replay(stored)),
+        Err(e) => {
+            return Ok(text_response(
+                StatusCode::InternalServerError,
+                &format!("Database error: {}", e),
+            ));
+        }
+    }
+
+    let mut res = match handler(req, db.clone()).await {
+        Ok(res) if !res.status().is_server_error() => res,
+        other => {
+            release(db, principal, key).await;
+            return other;
+        }
+    };
+    let content_type = res.content_type();
+    let body = res.body_string().await?;
+    res.set_body(body.clone());
+    if let Some(mime) = &content_type {
+        res.set_content_type(mime.clone());
+    }
+    let stored = StoredResponse {
+        status: res.status() as u16,
+        content_type: content_type.map(|mime| mime.to_string()),
+        location: res
+            .header("Location")
+            .map(|values| values.as_str().to_string()),
+        body,
+    };
+    let saved = smol::unblock(move || match db.lock() {
+        Ok(conn) => complete_idempotency_key(&conn, &principal, &key, &stored),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = saved {
+        eprintln!("Failed to store idempotent response: {}", e);
+    }
+    Ok(res)
+}
+
+async fn release(db: Db, principal: String, key: String) {
+    let released = smol::unblock(move || match db.lock() {
+        Ok(conn) => release_idempotency_key(&conn, &principal, &key),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = released {
+        eprintln!("Failed to release Idempotency-Key: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+    use std::cell::Cell;
+
+    fn request(body: &str) -> Request {
+        let mut req = Request::new(Method::Post, "http://localhost/add");
+        req.set_body(body.to_string());
+        req
+    }
+
+    async fn ok(_req: Request, _db: Db) -> http_types::Result<Response> {
+        Ok(text_response(StatusCode::Ok, "done"))
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let mut req = Request::new(Method::Post, "http://localhost/add");
+        assert_eq!(idempotency_key(&req), None);
+        req.insert_header("Idempotency-Key", " retry-1 ");
+        assert_eq!(idempotency_key(&req).as_deref(), Some("retry-1"));
+        let mut get = Request::new(Method::Get, "http://localhost/jobs/1");
+        get.insert_header("Idempotency-Key", "retry-1");
+        assert_eq!(idempotency_key(&get), None);
+
+        assert!(is_valid_key("7f3c2a9e-1b4d-4c8e-9a61-0d2f5b7e8c13"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("has space"));
+        assert!(!is_valid_key(&"k".repeat(256)));
+
+        let other = Request::new(Method::Post, "http://localhost/del");
+        assert_eq!(fingerprint(&req, b"{}"), fingerprint(&req, b"{}"));
+        assert_ne!(fingerprint(&req, b"{}"), fingerprint(&req, b"{ }"));
+        assert_ne!(fingerprint(&req, b"{}"), fingerprint(&other, b"{}"));
+    }
+
+    #[test]
+    fn test_in_progress_key_is_rejected() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let res = idempotent("k".to_string(), request("{}"), h.db.clone(), |_req, db| {
+                async move {
+                    // 第一个请求尚未完成时重试
+                    let res = idempotent("k".to_string(), request("{}"), db, ok).await?;
+                    assert_eq!(res.status(), StatusCode::Conflict);
+                    assert_eq!(res.header("Retry-After").unwrap().as_str(), "1");
+                    Ok(text_response(StatusCode::Ok, "first"))
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(res.status(), StatusCode::Ok);
+        });
+    }
+
+    #[test]
+    fn test_key_is_released_after_server_error() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let res = idempotent("k".to_string(), request("{}"), h.db.clone(), |_, _| async {
+                Ok(text_response(StatusCode::InternalServerError, "failed"))
+            })
+            .await
+            .unwrap();
+            assert_eq!(res.status(), StatusCode::InternalServerError);
+
+            // 同一个 key 重试时重新执行
+            let calls = Cell::new(0);
+            let res = idempotent("k".to_string(), request("{}"), h.db.clone(), |req, db| {
+                calls.set(calls.get() + 1);
+                ok(req, db)
+            })
+            .await
+            .unwrap();
+            assert_eq!(res.status(), StatusCode::Ok);
+            assert_eq!(calls.get(), 1);
+        });
+    }
+}
@@ -0,0 +1,348 @@
+use crate::Db;
+use crate::audit::*;
+use crate::db::*;
+use crate::handler::*;
+use crate::model::*;
+use crate::rest::put_peer_flow;
+use crate::shutdown;
+use crate::system::{BirdReload, reconfigure_bird};
+use futures_lite::FutureExt;
+use http_types::{Request, Response, StatusCode};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use smol::channel::{Receiver, Sender};
+use std::time::Duration;
+
+const JOB_PATH_PREFIX: &str = "/jobs/";
+
+// 没有收到唤醒时也定期检查队列，例如另一进程写入的任务
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// 一批最多连续执行的任务数，之后执行 birdc configure 并记录这一批的结果
+const MAX_BATCH_JOBS: usize = 32;
+
+lazy_static! {
+    static ref WAKEUP: (Sender<()>, Receiver<()>) = smol::channel::bounded(1);
+}
+
+// 修改默认入队并立即返回 202，?wait=true 时等待执行完成后返回结果
+pub fn should_queue(req: &Request) -> bool {
+    !matches!(query_param(req, "wait").as_deref(), Some("true" | "1"))
+}
+
+fn wake_worker() {
+    WAKEUP.0.try_send(()).ok();
+}
+
+pub async fn enqueue_response<T: Serialize>(
+    req: &Request,
+    action: JobAction,
+    asn: u64,
+    payload: &T,
+    db: Db,
+) -> http_types::Result<Response> {
+    let payload = serde_json::to_string(payload)?;
+    let principal = request_principal(req);
+    let source_ip = request_source_ip(req);
+    let db_result: Result<i64, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => insert_job(
+            &conn,
+            action,
+            Some(asn),
+            &payload,
+            &principal,
+            source_ip.as_deref(),
+        ),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    let id = match db_result {
+        Ok(id) => id,
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            return Ok(res);
+        }
+    };
+    wake_worker();
+
+    let location = format!("{}{}", JOB_PATH_PREFIX, id);
+    let mut res = Response::new(StatusCode::Accepted);
+    res.insert_header("Location", location.as_str());
+    res.insert_header("Content-Type", "application/json; charset=utf-8");
+    res.set_body(serde_json::to_string(&JobAccepted {
+        job_id: id,
+        status: JobStatus::Queued,
+        location,
+    })?);
+    Ok(res)
+}
+
+// 执行任务，返回同步请求本应返回的状态码与消息；birdc configure 由 reload 决定何时执行
+async fn execute(job: &Job, db: Db, reload: BirdReload) -> Result<(StatusCode, String), OpError> {
+    let invalid_payload = |e: serde_json::Error| {
+        OpError::new(
+            StatusCode::BadRequest,
+            format!("Invalid job payload: {}", e),
+        )
+    };
+    match job.action {
+        JobAction::Add => {
+            let peer: Peer = serde_json::from_str(&job.payload).map_err(invalid_payload)?;
+            let asn = peer.asn;
+            match add_peer_flow(peer, db, false, reload).await? {
+                AddOutcome::Pending => Ok((
+                    StatusCode::Accepted,
+                    format!("Peer pending approval: {}", asn),
+                )),
+                AddOutcome::Unchanged => {
+                    Ok((StatusCode::Ok, format!("Peer already added: {}", asn)))
+                }
+                _ => Ok((StatusCode::Ok, format!("Peer added: {}", asn))),
+            }
+        }
+        JobAction::Update => {
+            let update: PeerUpdate = serde_json::from_str(&job.payload).map_err(invalid_payload)?;
+            let asn = update.asn;
+            update_peer_flow(update, db, reload).await?;
+            Ok((StatusCode::Ok, format!("Peer updated: {}", asn)))
+        }
+        JobAction::Del => {
+            let asn = job
+                .asn
+                .ok_or_else(|| OpError::new(StatusCode::BadRequest, "Job has no ASN"))?;
+            del_peer_flow(asn, db, reload).await?;
+            Ok((StatusCode::Ok, format!("Peer deleted: {}", asn)))
+        }
+        JobAction::Put => {
+            let peer: Peer = serde_json::from_str(&job.payload).map_err(invalid_payload)?;
+            let asn = peer.asn;
+            let status = put_peer_flow(peer, db, reload).await?;
+            let message = match status {
+                StatusCode::Ok => format!("Peer replaced: {}", asn),
+                StatusCode::Accepted => format!("Peer pending approval: {}", asn),
+                _ => format!("Peer added: {}", asn),
+            };
+            Ok((status, message))
+        }
+    }
+}
+
+async fn finish(job: Job, result: Result<(StatusCode, String), OpError>, db: Db) {
+    let (status, result_status, message) = match result {
+        Ok((code, message)) => (JobStatus::Succeeded, code, message),
+        Err(e) => (JobStatus::Failed, e.status, e.message),
+    };
+    println!("Job {} {}: {}", job.id, status.as_str(), message);
+
+    let id = job.id;
+    let db_clone = db.clone();
+    let result = message.clone();
+    let db_result = smol::unblock(move || match db_clone.lock() {
+        Ok(conn) => finish_job(&conn, id, status, result_status as u16, &result),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    if let Err(e) = db_result {
+        eprintln!("Failed to record result of job {}: {}", id, e);
+    }
+    record_job_result(&db, &job, result_status, &message).await;
+}
+
+// 依次执行队列中的任务，期间只写入配置；全部执行后只执行一次 birdc configure，再记录每个任务的结果
+// 关闭过程中不再取新任务，返回执行的任务数
+async fn run_batch(db: Db) -> usize {
+    let mut batch = Vec::new();
+    while batch.len() < MAX_BATCH_JOBS && !shutdown::is_shutting_down() {
+        let db_clone = db.clone();
+        let claimed = smol::unblock(move || match db_clone.lock() {
+            Ok(conn) => claim_next_job(&conn),
+            Err(e) => Err(PeerDbError::LockError(e.to_string())),
+        })
+        .await;
+        let job = match claimed {
+            Ok(Some(job)) => job,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to fetch the next job: {}", e);
+                break;
+            }
+        };
+        let reload = BirdReload::deferred();
+        let result = execute(&job, db.clone(), reload.clone()).await;
+        batch.push((job, result, reload));
+    }
+
+    let needs_reload =
+        |(_, result, reload): &(Job, _, BirdReload)| Result::is_ok(result) && reload.is_pending();
+    if batch.iter().any(needs_reload)
+        && let Err(e) = smol::unblock(reconfigure_bird).await
+    {
+        for entry in batch.iter_mut().filter(|entry| needs_reload(entry)) {
+            entry.1 = Err(OpError::new(
+                StatusCode::InternalServerError,
+                format!("Failed to apply config: {}", e),
+            ));
+        }
+    }
+
+    let count = batch.len();
+    for (job, result, _) in batch {
+        finish(job, result, db.clone()).await;
+    }
+    count
+}
+
+// 按入队顺序分批执行任务，正在执行的一批计入 in-flight
+pub async fn run_worker(db: Db) {
+    loop {
+        let Some(guard) = shutdown::InFlightGuard::enter() else {
+            return;
+        };
+        let count = run_batch(db.clone()).await;
+        drop(guard);
+        if count > 0 {
+            continue;
+        }
+        async {
+            WAKEUP.1.recv().await.ok();
+        }
+        .or(async {
+            smol::Timer::after(IDLE_POLL_INTERVAL).await;
+        })
+        .await;
+    }
+}
+
+pub async fn handle_get_job(req: Request, db: Db) -> http_types::Result<Response> {
+    let id = req
+        .url()
+        .path()
+        .strip_prefix(JOB_PATH_PREFIX)
+        .and_then(|id| id.parse::<i64>().ok());
+    let Some(id) = id else {
+        let mut res = Response::new(StatusCode::NotFound);
+        res.set_body("Not Found\n".to_string());
+        return Ok(res);
+    };
+    let db_result: Result<Job, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => get_job(&conn, id),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    match db_result {
+        Ok(job) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header("Content-Type", "application/json; charset=utf-8");
+            res.set_body(serde_json::to_string(&job)?);
+            Ok(res)
+        }
+        Err(PeerDbError::NotFound) => {
+            let mut res = Response::new(StatusCode::NotFound);
+            res.set_body(format!("Job not found: {}", id));
+            Ok(res)
+        }
+        Err(e) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("Database error: {}", e));
+            Ok(res)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+    use http_types::Method;
+
+    #[test]
+    fn test_should_queue() {
+        let req = Request::new(Method::Post, "http://localhost/add");
+        assert!(should_queue(&req));
+        let req = Request::new(Method::Post, "http://localhost/add?wait=true");
+        assert!(!should_queue(&req));
+        let req = Request::new(Method::Post, "http://localhost/add?dry_run=true&wait=1");
+        assert!(!should_queue(&req));
+        let req = Request::new(Method::Post, "http://localhost/add?wait=false");
+        assert!(should_queue(&req));
+    }
+
+    #[test]
+    fn test_run_batch_reconfigures_once() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let ids: Vec<i64> = {
+                let conn = h.db.lock().unwrap();
+                (1..=3u64)
+                    .map(|i| {
+                        let asn = 4242420253 + i;
+                        let peer = serde_json::json!({
+                            "asn": asn,
+                            "wireguard_endpoint": "peer.example.net:51820",
+                            "wireguard_link_local": format!("fe80::{}", i + 1),
+                            "wireguard_public_key": "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=",
+                        });
+                        insert_job(
+                            &conn,
+                            JobAction::Add,
+                            Some(asn),
+                            &peer.to_string(),
+                            "test",
+                            None,
+                        )
+                        .unwrap()
+                    })
+                    .collect()
+            };
+
+            assert_eq!(run_batch(h.db.clone()).await, 3);
+            let commands = h.backend.commands();
+            let configures = commands
+                .iter()
+                .filter(|command| *command == "/usr/sbin/birdc configure")
+                .count();
+            assert_eq!(configures, 1, "{:?}", commands);
+            assert_eq!(commands.last().unwrap(), "/usr/sbin/birdc configure");
+
+            let conn = h.db.lock().unwrap();
+            for id in ids {
+                let job = get_job(&conn, id).unwrap();
+                assert_eq!(job.status, JobStatus::Succeeded, "{}", job.result);
+            }
+        });
+    }
+
+    #[test]
+    fn test_run_batch_reload_failure() {
+        let h = Harness::setup();
+        smol::block_on(async {
+            let id = {
+                let conn = h.db.lock().unwrap();
+                let peer = serde_json::json!({
+                    "asn": 4242420253u64,
+                    "wireguard_endpoint": "peer.example.net:51820",
+                    "wireguard_link_local": "fe80::1",
+                    "wireguard_public_key": "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=",
+                });
+                insert_job(
+                    &conn,
+                    JobAction::Add,
+                    Some(4242420253),
+                    &peer.to_string(),
+                    "test",
+                    None,
+                )
+                .unwrap()
+            };
+            h.backend
+                .fail_on("/usr/sbin/birdc configure", "injected failure");
+
+            assert_eq!(run_batch(h.db.clone()).await, 1);
+            let job = get_job(&h.db.lock().unwrap(), id).unwrap();
+            assert_eq!(job.status, JobStatus::Failed);
+            assert_eq!(job.result_status, Some(500));
+            assert!(job.result.contains("injected failure"), "{}", job.result);
+        });
+    }
+}
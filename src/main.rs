@@ -1,21 +1,43 @@
 use crate::db::*;
 use crate::handler::*;
 use async_io::Async;
+use async_signal::{Signal, Signals};
 use futures_lite::io::{AsyncRead, AsyncWrite};
-use lazy_static::lazy_static;
+use futures_lite::{FutureExt, StreamExt};
+use http_types::{Request, Response, StatusCode};
 use serde::Deserialize;
+use smol::lock::Semaphore;
 use std::fs;
-use std::io::{self, Read};
-use std::net::TcpListener;
+use std::io;
 use std::net::ToSocketAddrs;
-use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::Duration;
+mod adopt;
+mod approval;
+mod audit;
+mod cli;
+mod connection;
 mod db;
+mod dry_run;
 mod gen_config;
 mod handler;
+mod idempotency;
+mod jobs;
 mod model;
+mod openapi;
+mod ratelimit;
+mod rest;
+mod shutdown;
 mod system;
+mod systemd;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod unix_socket;
 
 #[derive(Deserialize, Debug)]
 struct ApiConfig {
@@ -23,6 +45,53 @@ struct ApiConfig {
     listen_address_v4: String,
     listen_address_v6: String,
     secret: String,
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
+    #[serde(default = "default_max_connections")]
+    max_connections: usize,
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+    #[serde(default = "default_header_timeout")]
+    header_timeout: u64,
+    #[serde(default = "default_read_timeout")]
+    read_timeout: u64,
+    #[serde(default = "default_idle_timeout")]
+    idle_timeout: u64,
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_max_connections() -> usize {
+    256
+}
+
+fn default_max_body_size() -> usize {
+    1024 * 1024
+}
+
+fn default_header_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    30
+}
+
+fn default_idle_timeout() -> u64 {
+    60
+}
+
+impl ApiConfig {
+    fn connection_limits(&self) -> connection::Limits {
+        connection::Limits {
+            idle_timeout: Duration::from_secs(self.idle_timeout),
+            header_timeout: Duration::from_secs(self.header_timeout),
+            read_timeout: Duration::from_secs(self.read_timeout),
+            max_body_size: self.max_body_size,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -30,6 +99,27 @@ struct PeerConfig {
     link_local: String,
     wireguard_private_key: String,
     port_prefix_number: u16,
+    #[serde(default = "default_allowed_ips")]
+    allowed_ips: Vec<String>,
+    #[serde(default = "default_interface_prefix")]
+    interface_prefix: String,
+}
+
+fn default_interface_prefix() -> String {
+    "dn42_".to_string()
+}
+
+fn default_allowed_ips() -> Vec<String> {
+    [
+        "10.0.0.0/8",
+        "172.20.0.0/14",
+        "172.31.0.0/16",
+        "fd00::/8",
+        "fe80::/64",
+    ]
+    .iter()
+    .map(|cidr| cidr.to_string())
+    .collect()
 }
 
 #[derive(Deserialize, Debug)]
@@ -39,6 +129,116 @@ struct EnvironmentConfig {
     rc_update_path: String,
     systemctl_path: String,
     birdc_path: String,
+    #[serde(default = "default_birdc_configure_delay_ms")]
+    birdc_configure_delay_ms: u64,
+}
+
+fn default_birdc_configure_delay_ms() -> u64 {
+    200
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ApprovalConfig {
+    require_approval: bool,
+    auto_approve: bool,
+    registry_path: String,
+    check_endpoint_reachable: bool,
+    ping_path: String,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        ApprovalConfig {
+            require_approval: false,
+            auto_approve: false,
+            registry_path: String::new(),
+            check_endpoint_reachable: false,
+            ping_path: "/bin/ping".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct PathsConfig {
+    database: String,
+    wireguard_dir: String,
+    bird_peers_dir: String,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            database: "peers.db".to_string(),
+            wireguard_dir: "/etc/wireguard".to_string(),
+            bird_peers_dir: "/etc/bird/peers".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct TlsConfig {
+    cert_path: String,
+    key_path: String,
+    client_ca_path: String,
+    require_client_cert: bool,
+}
+
+impl TlsConfig {
+    fn enabled(&self) -> bool {
+        !self.cert_path.is_empty()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct UnixSocketConfig {
+    path: String,
+    owner: String,
+    group: String,
+    mode: String,
+    trusted_uids: Vec<u32>,
+}
+
+impl Default for UnixSocketConfig {
+    fn default() -> Self {
+        UnixSocketConfig {
+            path: String::new(),
+            owner: String::new(),
+            group: String::new(),
+            mode: "0660".to_string(),
+            trusted_uids: Vec::new(),
+        }
+    }
+}
+
+// rate 为每秒补充的请求数，0 表示不限速；max_failed_auth 为 0 表示不锁定
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct RateLimitConfig {
+    per_ip_rate: f64,
+    per_ip_burst: u32,
+    per_token_rate: f64,
+    per_token_burst: u32,
+    max_failed_auth: u32,
+    failed_auth_window: u64,
+    lockout_seconds: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            per_ip_rate: 5.0,
+            per_ip_burst: 20,
+            per_token_rate: 10.0,
+            per_token_burst: 50,
+            max_failed_auth: 5,
+            failed_auth_window: 60,
+            lockout_seconds: 300,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -49,153 +249,676 @@ struct AppConfig {
     peer: PeerConfig,
     #[serde(rename = "Environment")]
     env: EnvironmentConfig,
+    #[serde(rename = "Approval", default)]
+    approval: ApprovalConfig,
+    #[serde(rename = "Paths", default)]
+    paths: PathsConfig,
+    #[serde(rename = "TLS", default)]
+    tls: TlsConfig,
+    #[serde(rename = "UnixSocket", default)]
+    unix_socket: UnixSocketConfig,
+    #[serde(rename = "RateLimit", default)]
+    rate_limit: RateLimitConfig,
 }
 
-lazy_static! {
-    static ref CONFIG: AppConfig = {
-        let config_path = "config.toml";
+const CONFIG_PATH_ENV: &str = "DN42_AUTOPEER_CONFIG";
+const DB_PATH_ENV: &str = "DN42_AUTOPEER_DB";
 
-        let mut file = fs::File::open(config_path)
-            .expect(&format!("Failed to open config file: {}", config_path));
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Failed to read config file content");
+// 由命令行参数设置，优先于环境变量与配置文件
+static CONFIG_PATH_OVERRIDE: OnceLock<String> = OnceLock::new();
+static DB_PATH_OVERRIDE: OnceLock<String> = OnceLock::new();
 
-        toml::from_str(&contents).expect("Failed to parse TOML configuration")
-    };
+// 配置文件路径：--config > DN42_AUTOPEER_CONFIG > ./config.toml
+fn config_path() -> String {
+    CONFIG_PATH_OVERRIDE
+        .get()
+        .cloned()
+        .or_else(|| std::env::var(CONFIG_PATH_ENV).ok())
+        .filter(|path| !path.is_empty())
+        .unwrap_or_else(|| "config.toml".to_string())
 }
 
-#[derive(Clone)]
-struct CloneableStream {
-    inner: Arc<Mutex<Async<std::net::TcpStream>>>,
+// 数据库路径：--db > DN42_AUTOPEER_DB > Paths.database
+fn db_path() -> String {
+    DB_PATH_OVERRIDE
+        .get()
+        .cloned()
+        .or_else(|| std::env::var(DB_PATH_ENV).ok())
+        .filter(|path| !path.is_empty())
+        .unwrap_or_else(|| config().paths.database.clone())
 }
 
-impl AsyncRead for CloneableStream {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        let mut inner = self.inner.lock().unwrap();
-        Pin::new(&mut *inner).poll_read(cx, buf)
+// 当前生效的配置，SIGHUP 时整体替换
+static CONFIG: RwLock<Option<Arc<AppConfig>>> = RwLock::new(None);
+
+fn load_config(path: &str) -> Result<AppConfig, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse config file {}: {}", path, e))
+}
+
+// 返回当前配置的快照，一次操作内应只获取一次以保证前后一致
+fn config() -> Arc<AppConfig> {
+    if let Some(config) = CONFIG.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return config.clone();
     }
+    CONFIG
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(|| {
+            Arc::new(load_config(&config_path()).unwrap_or_else(|e| panic!("{}", e)))
+        })
+        .clone()
+}
+
+fn set_config(config: AppConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(config));
+}
+
+// 替换当前配置并返回之前的配置，None 表示下次使用时从配置文件加载
+#[cfg(test)]
+fn replace_config(config: Option<Arc<AppConfig>>) -> Option<Arc<AppConfig>> {
+    std::mem::replace(
+        &mut *CONFIG.write().unwrap_or_else(|e| e.into_inner()),
+        config,
+    )
+}
+
+// 读取并校验配置，成功后才替换当前配置
+fn load_and_check_config(check_environment: bool) -> Result<AppConfig, String> {
+    let config = load_config(&config_path())?;
+    check_config(&config, check_environment)?;
+    Ok(config)
 }
 
-impl AsyncWrite for CloneableStream {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
-        let mut inner = self.inner.lock().unwrap();
-        Pin::new(&mut *inner).poll_write(cx, buf)
+// 已绑定的监听地址与打开的数据库无法在运行时更换
+fn restart_required_changes(old: &AppConfig, new: &AppConfig) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if old.api.api_port != new.api.api_port {
+        changes.push("API.api_port");
+    }
+    if old.api.listen_address_v4 != new.api.listen_address_v4 {
+        changes.push("API.listen_address_v4");
+    }
+    if old.api.listen_address_v6 != new.api.listen_address_v6 {
+        changes.push("API.listen_address_v6");
+    }
+    if old.api.max_connections != new.api.max_connections {
+        changes.push("API.max_connections");
+    }
+    if old.paths.database != new.paths.database {
+        changes.push("Paths.database");
+    }
+    // 证书内容变化会自动重新加载，但路径与客户端认证方式只在启动时读取
+    if old.tls.cert_path != new.tls.cert_path || old.tls.key_path != new.tls.key_path {
+        changes.push("TLS.cert_path/key_path");
+    }
+    if old.tls.client_ca_path != new.tls.client_ca_path
+        || old.tls.require_client_cert != new.tls.require_client_cert
+    {
+        changes.push("TLS.client_ca_path/require_client_cert");
     }
+    if old.unix_socket.path != new.unix_socket.path
+        || old.unix_socket.owner != new.unix_socket.owner
+        || old.unix_socket.group != new.unix_socket.group
+        || old.unix_socket.mode != new.unix_socket.mode
+    {
+        changes.push("UnixSocket.path/owner/group/mode");
+    }
+    changes
+}
+
+// 已部署的隧道只有在 /resync 后才会使用新值
+fn resync_required_changes(old: &AppConfig, new: &AppConfig) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if old.peer.link_local != new.peer.link_local {
+        changes.push("Peer.link_local");
+    }
+    if old.peer.wireguard_private_key != new.peer.wireguard_private_key {
+        changes.push("Peer.wireguard_private_key");
+    }
+    if old.peer.allowed_ips != new.peer.allowed_ips {
+        changes.push("Peer.allowed_ips");
+    }
+    changes
+}
+
+// 已部署的 peer 的接口名、监听端口与服务名由这些配置决定，运行中修改会使它们无法被管理
+fn rejected_reload_changes(old: &AppConfig, new: &AppConfig) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if old.peer.interface_prefix != new.peer.interface_prefix {
+        changes.push("Peer.interface_prefix");
+    }
+    if old.peer.port_prefix_number != new.peer.port_prefix_number {
+        changes.push("Peer.port_prefix_number");
+    }
+    if old.env.init_system != new.env.init_system {
+        changes.push("Environment.init_system");
+    }
+    changes
+}
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        let mut inner = self.inner.lock().unwrap();
-        Pin::new(&mut *inner).poll_flush(cx)
+// 重新加载后立即生效，但只影响之后的操作
+fn deferred_reload_changes(old: &AppConfig, new: &AppConfig) -> Vec<(&'static str, &'static str)> {
+    let mut changes = Vec::new();
+    if old.paths.wireguard_dir != new.paths.wireguard_dir
+        || old.paths.bird_peers_dir != new.paths.bird_peers_dir
+    {
+        changes.push((
+            "Paths.wireguard_dir/bird_peers_dir",
+            "new peers are written there, files of existing peers are not moved",
+        ));
+    }
+    if old.api.shutdown_timeout != new.api.shutdown_timeout {
+        changes.push(("API.shutdown_timeout", "used by the next shutdown"));
+    }
+    if old.api.max_body_size != new.api.max_body_size
+        || old.api.header_timeout != new.api.header_timeout
+        || old.api.read_timeout != new.api.read_timeout
+        || old.api.idle_timeout != new.api.idle_timeout
+    {
+        changes.push((
+            "API.max_body_size/header_timeout/read_timeout/idle_timeout",
+            "applies to new connections",
+        ));
     }
+    changes
+}
 
-    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        let mut inner = self.inner.lock().unwrap();
-        Pin::new(&mut *inner).poll_close(cx)
+fn reload_config() {
+    println!("SIGHUP received, reloading config from {}", config_path());
+    let new_config = match load_and_check_config(true) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Config reload failed, keeping the current config: {}", e);
+            return;
+        }
+    };
+    let old_config = config();
+    let rejected = rejected_reload_changes(&old_config, &new_config);
+    if !rejected.is_empty() {
+        eprintln!(
+            "Config reload rejected, keeping the current config: {} cannot change while the daemon is running, deployed peers would no longer match",
+            rejected.join(", ")
+        );
+        return;
+    }
+    let restart_required = restart_required_changes(&old_config, &new_config);
+    let resync_required = resync_required_changes(&old_config, &new_config);
+    let deferred = deferred_reload_changes(&old_config, &new_config);
+    set_config(new_config);
+    println!("Config reloaded");
+    for (setting, note) in deferred {
+        println!("{} changed, {}", setting, note);
+    }
+    for setting in restart_required {
+        println!(
+            "Warning: {} changed, restart the daemon for it to take effect",
+            setting
+        );
+    }
+    for setting in resync_required {
+        println!(
+            "Warning: {} changed, run POST /resync to apply it to existing peers",
+            setting
+        );
     }
 }
 
 pub type Db = Arc<Mutex<rusqlite::Connection>>;
 
-fn check_config() -> Result<(), String> {
-    if CONFIG.peer.link_local.is_empty() {
-        Err("No link-local address found".to_string())
-    } else if CONFIG.peer.wireguard_private_key.is_empty() {
-        Err("No wireguard private key found".to_string())
-    } else if CONFIG.api.api_port == 0 {
-        Err("Invalid API port".to_string())
-    } else if CONFIG.api.listen_address_v4.is_empty() && CONFIG.api.listen_address_v6.is_empty() {
-        Err("No listen address found".to_string())
-    } else if CONFIG.peer.port_prefix_number == 0 || CONFIG.peer.port_prefix_number >= 6 {
-        Err("Port prefix number must be between 1 and 5".to_string())
-    } else if CONFIG.env.init_system != "systemd" && CONFIG.env.init_system != "openrc" {
-        Err("Unsupported init system".to_string())
-    } else if CONFIG.env.init_system == "systemd" && CONFIG.env.systemctl_path.is_empty() {
-        Err("Environment: systemctl binary path is empty".to_string())
-    } else if CONFIG.env.init_system == "openrc" && CONFIG.env.rc_service_path.is_empty() {
-        Err("Environment: rc-service binary path is empty".to_string())
-    } else if CONFIG.env.init_system == "openrc" && CONFIG.env.rc_update_path.is_empty() {
-        Err("Environment: rc-update binary path is empty".to_string())
-    } else if CONFIG.env.birdc_path.is_empty() {
-        Err("Environment: birdc binary path is empty".to_string())
-    } else {
-        if CONFIG.api.secret.trim().is_empty() {
-            println!("Warning: API secret is empty");
+// 检查配置项本身的取值，不访问文件系统
+fn config_value_problems(config: &AppConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if config.api.api_port == 0 {
+        problems.push("API.api_port: must not be 0".to_string());
+    }
+    if config.api.listen_address_v4.is_empty()
+        && config.api.listen_address_v6.is_empty()
+        && config.unix_socket.path.is_empty()
+    {
+        problems.push("API: no listen address or UnixSocket.path configured".to_string());
+    }
+    for (name, value) in [
+        ("max_connections", config.api.max_connections as u64),
+        ("max_body_size", config.api.max_body_size as u64),
+        ("header_timeout", config.api.header_timeout),
+        ("read_timeout", config.api.read_timeout),
+        ("idle_timeout", config.api.idle_timeout),
+    ] {
+        if value == 0 {
+            problems.push(format!("API.{}: must not be 0", name));
         }
-        Ok(())
     }
+
+    if config.env.birdc_configure_delay_ms > 10_000 {
+        problems.push("Environment.birdc_configure_delay_ms: must be at most 10000".to_string());
+    }
+
+    if config.peer.link_local.is_empty() {
+        problems.push("Peer.link_local: is empty".to_string());
+    } else if !model::is_valid_link_local(&config.peer.link_local) {
+        problems.push(format!(
+            "Peer.link_local: {} is not an IPv6 link-local address (fe80::/10, without prefix length)",
+            config.peer.link_local
+        ));
+    }
+    if config.peer.wireguard_private_key.is_empty() {
+        problems.push("Peer.wireguard_private_key: is empty".to_string());
+    } else if !model::is_valid_wireguard_key(&config.peer.wireguard_private_key) {
+        problems
+            .push("Peer.wireguard_private_key: must be a base64 encoded 32-byte key".to_string());
+    }
+    if config.peer.port_prefix_number == 0 || config.peer.port_prefix_number >= 6 {
+        problems.push("Peer.port_prefix_number: must be between 1 and 5".to_string());
+    }
+    if config.peer.allowed_ips.is_empty() {
+        problems.push("Peer.allowed_ips: is empty".to_string());
+    }
+    for cidr in config
+        .peer
+        .allowed_ips
+        .iter()
+        .filter(|cidr| !model::is_valid_cidr(cidr))
+    {
+        problems.push(format!("Peer.allowed_ips: invalid CIDR {}", cidr));
+    }
+    if !model::is_valid_interface_prefix(&config.peer.interface_prefix) {
+        problems.push(format!(
+            "Peer.interface_prefix: must be 1 to {} letters, digits, '_' or '-'",
+            model::MAX_INTERFACE_PREFIX_LENGTH
+        ));
+    }
+
+    if config.paths.database.is_empty() {
+        problems.push("Paths.database: is empty".to_string());
+    }
+    if config.paths.wireguard_dir.is_empty() {
+        problems.push("Paths.wireguard_dir: is empty".to_string());
+    }
+    if config.paths.bird_peers_dir.is_empty() {
+        problems.push("Paths.bird_peers_dir: is empty".to_string());
+    }
+
+    if config.env.init_system != "systemd" && config.env.init_system != "openrc" {
+        problems.push(format!(
+            "Environment.init_system: unsupported init system {:?}, expected \"systemd\" or \"openrc\"",
+            config.env.init_system
+        ));
+    }
+    if config.approval.check_endpoint_reachable && config.approval.ping_path.is_empty() {
+        problems.push("Approval.ping_path: is empty".to_string());
+    }
+
+    if !config.unix_socket.path.is_empty() {
+        if !config.unix_socket.path.starts_with('/') {
+            problems.push("UnixSocket.path: must be an absolute path".to_string());
+        }
+        if unix_socket::parse_mode(&config.unix_socket.mode).is_none() {
+            problems.push(format!(
+                "UnixSocket.mode: {:?} is not an octal permission such as \"0660\"",
+                config.unix_socket.mode
+            ));
+        }
+    }
+
+    if config.tls.cert_path.is_empty() != config.tls.key_path.is_empty() {
+        problems.push("TLS: cert_path and key_path must be set together".to_string());
+    }
+    if !config.tls.client_ca_path.is_empty() && !config.tls.enabled() {
+        problems.push("TLS.client_ca_path: requires cert_path and key_path".to_string());
+    }
+    if config.tls.require_client_cert && config.tls.client_ca_path.is_empty() {
+        problems.push("TLS.require_client_cert: requires client_ca_path".to_string());
+    }
+
+    let rate_limit = &config.rate_limit;
+    for (name, rate, burst) in [
+        ("per_ip", rate_limit.per_ip_rate, rate_limit.per_ip_burst),
+        (
+            "per_token",
+            rate_limit.per_token_rate,
+            rate_limit.per_token_burst,
+        ),
+    ] {
+        if !rate.is_finite() || rate < 0.0 {
+            problems.push(format!(
+                "RateLimit.{}_rate: must be a non-negative number",
+                name
+            ));
+        } else if rate > 0.0 && burst == 0 {
+            problems.push(format!("RateLimit.{}_burst: must be at least 1", name));
+        }
+    }
+    if rate_limit.max_failed_auth > 0 {
+        if rate_limit.failed_auth_window == 0 {
+            problems.push("RateLimit.failed_auth_window: must not be 0".to_string());
+        }
+        if rate_limit.lockout_seconds == 0 {
+            problems.push("RateLimit.lockout_seconds: must not be 0".to_string());
+        }
+    }
+
+    problems
 }
 
-fn main() -> std::io::Result<()> {
-    if let Err(err_string) = check_config() {
-        eprintln!("Error: {}", err_string);
-        return Err(io::Error::new(io::ErrorKind::Other, err_string));
+fn check_executable(name: &str, path: &str) -> Option<String> {
+    if path.is_empty() {
+        return Some(format!("{}: is empty", name));
+    }
+    match fs::metadata(path) {
+        Ok(meta) if !meta.is_file() => Some(format!("{}: {} is not a file", name, path)),
+        Ok(meta) if meta.permissions().mode() & 0o111 == 0 => {
+            Some(format!("{}: {} is not executable", name, path))
+        }
+        Ok(_) => None,
+        Err(e) => Some(format!("{}: {}: {}", name, path, e)),
     }
+}
 
-    let conn = rusqlite::Connection::open("peers.db").expect("Failed to open peers.db");
-    init_db(&conn).expect("Failed to initialize database");
-    let db: Db = Arc::new(Mutex::new(conn));
+// 通过创建临时文件确认目录可写，比检查权限位更能反映实际情况（ACL、只读挂载等）
+fn check_writable_dir(name: &str, path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    match fs::metadata(path) {
+        Ok(meta) if !meta.is_dir() => {
+            return Some(format!("{}: {} is not a directory", name, path));
+        }
+        Ok(_) => {}
+        Err(e) => return Some(format!("{}: {}: {}", name, path, e)),
+    }
+    let probe = Path::new(path).join(format!(".dn42-autopeer-write-test-{}", std::process::id()));
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            fs::remove_file(&probe).ok();
+            None
+        }
+        Err(e) => Some(format!("{}: {} is not writable: {}", name, path, e)),
+    }
+}
 
-    let (ctrlc_sender, ctrlc_receiver) = async_channel::bounded(100);
-    let ctrlc_handle = move || {
-        ctrlc_sender.try_send(()).ok();
+// 检查配置引用的外部程序与目录是否可用
+fn config_environment_problems(config: &AppConfig) -> Vec<String> {
+    let mut binaries = vec![("Environment.birdc_path", &config.env.birdc_path)];
+    match config.env.init_system.as_str() {
+        "systemd" => binaries.push(("Environment.systemctl_path", &config.env.systemctl_path)),
+        "openrc" => {
+            binaries.push(("Environment.rc_service_path", &config.env.rc_service_path));
+            binaries.push(("Environment.rc_update_path", &config.env.rc_update_path));
+        }
+        _ => {}
+    }
+    if config.approval.check_endpoint_reachable && !config.approval.ping_path.is_empty() {
+        binaries.push(("Approval.ping_path", &config.approval.ping_path));
+    }
+
+    let mut problems: Vec<String> = binaries
+        .into_iter()
+        .filter_map(|(name, path)| check_executable(name, path))
+        .collect();
+    problems.extend(check_writable_dir(
+        "Paths.wireguard_dir",
+        &config.paths.wireguard_dir,
+    ));
+    problems.extend(check_writable_dir(
+        "Paths.bird_peers_dir",
+        &config.paths.bird_peers_dir,
+    ));
+    if config.tls.enabled() && !config.tls.key_path.is_empty() {
+        problems.extend(
+            tls::load_certified_key(&config.tls.cert_path, &config.tls.key_path)
+                .err()
+                .map(|e| format!("TLS: {}", e)),
+        );
+    }
+    if config.tls.enabled() && !config.tls.client_ca_path.is_empty() {
+        problems.extend(
+            tls::load_client_verifier(&config.tls.client_ca_path, config.tls.require_client_cert)
+                .err()
+                .map(|e| format!("TLS.client_ca_path: {}", e)),
+        );
+    }
+    if !config.unix_socket.path.is_empty() {
+        let parent = Path::new(&config.unix_socket.path)
+            .parent()
+            .and_then(|dir| dir.to_str())
+            .unwrap_or("/");
+        problems.extend(check_writable_dir("UnixSocket.path", parent));
+        if !config.unix_socket.owner.is_empty() {
+            problems.extend(
+                unix_socket::lookup_user(&config.unix_socket.owner)
+                    .err()
+                    .map(|e| format!("UnixSocket.owner: {}", e)),
+            );
+        }
+        if !config.unix_socket.group.is_empty() {
+            problems.extend(
+                unix_socket::lookup_group(&config.unix_socket.group)
+                    .err()
+                    .map(|e| format!("UnixSocket.group: {}", e)),
+            );
+        }
+    }
+    if !config.approval.registry_path.is_empty() {
+        let aut_num = Path::new(&config.approval.registry_path).join("data/aut-num");
+        if !aut_num.is_dir() {
+            problems.push(format!(
+                "Approval.registry_path: {} does not exist",
+                aut_num.display()
+            ));
+        }
+    }
+    problems
+}
+
+fn print_config_warnings(config: &AppConfig) {
+    if config.approval.require_approval
+        && config.approval.auto_approve
+        && config.approval.registry_path.is_empty()
+        && !config.approval.check_endpoint_reachable
+    {
+        println!("Warning: auto_approve is enabled without any checks, every request is approved");
+    }
+    if config.api.secret.trim().is_empty() {
+        println!("Warning: API secret is empty");
+    }
+    if !config.tls.enabled() && !config.api.secret.trim().is_empty() {
+        for address in [&config.api.listen_address_v4, &config.api.listen_address_v6] {
+            if address
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| !ip.is_loopback())
+            {
+                println!(
+                    "Warning: TLS is disabled, the API secret is sent in cleartext on {}",
+                    address
+                );
+            }
+        }
+    }
+}
+
+// 一次性报告所有问题；check_environment 为 false 时只检查取值（只读命令无需访问系统）
+fn check_config(config: &AppConfig, check_environment: bool) -> Result<(), String> {
+    let mut problems = config_value_problems(config);
+    if check_environment {
+        problems.extend(config_environment_problems(config));
+    }
+    if !problems.is_empty() {
+        return Err(format!(
+            "Invalid configuration ({} problems):\n  - {}",
+            problems.len(),
+            problems.join("\n  - ")
+        ));
+    }
+    print_config_warnings(config);
+    Ok(())
+}
+
+// 守护进程重启后，确保已禁用的 peer 不会因其他原因被重新拉起
+fn enforce_disabled_peers(conn: &rusqlite::Connection) {
+    let peers = match list_peers(conn) {
+        Ok(peers) => peers,
+        Err(e) => {
+            eprintln!("Failed to list peers: {}", e);
+            return;
+        }
     };
-    ctrlc::set_handler(ctrlc_handle).unwrap();
+    for peer in peers
+        .iter()
+        .filter(|p| p.state == model::PeerState::Disabled)
+    {
+        if let Err(e) = system::enforce_disabled(&peer.interface_name) {
+            eprintln!("Failed to keep peer {} disabled: {}", peer.asn, e);
+        }
+    }
+}
 
-    let mut listeners = Vec::new();
+// 打开数据库并应用尚未执行的迁移
+fn open_database() -> io::Result<rusqlite::Connection> {
+    let path = db_path();
+    let conn =
+        open_db(&path).map_err(|e| io::Error::other(format!("Failed to open {}: {}", path, e)))?;
+    init_db(&conn)
+        .map_err(|e| io::Error::other(format!("Failed to initialize database: {}", e)))?;
+    Ok(conn)
+}
 
-    if !CONFIG.api.listen_address_v4.is_empty() {
-        let bind_address_v4 = format!("{}:{}", CONFIG.api.listen_address_v4, CONFIG.api.api_port);
-        let sock_addr_v4 = bind_address_v4
-            .to_socket_addrs()?
-            .next()
-            .ok_or(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Could not resolve address",
-            ))?;
-        let listener_v4 = Async::<TcpListener>::bind(sock_addr_v4)?;
-        listeners.push(listener_v4);
-        println!(
-            "Server running on http://{}:{}",
-            sock_addr_v4.ip(),
-            sock_addr_v4.port()
-        );
+fn serve() -> io::Result<()> {
+    let conn = open_database()?;
+    enforce_disabled_peers(&conn);
+    match requeue_interrupted_jobs(&conn) {
+        Ok(0) => {}
+        Ok(queued) => println!("Resuming {} queued jobs", queued),
+        Err(e) => eprintln!("Failed to requeue interrupted jobs: {}", e),
+    }
+    if let Err(e) = release_unfinished_idempotency_keys(&conn) {
+        eprintln!("Failed to release unfinished idempotency keys: {}", e);
     }
+    let db: Db = Arc::new(Mutex::new(conn));
 
-    if !CONFIG.api.listen_address_v6.is_empty() {
-        let bind_address_v6 = format!("{}:{}", CONFIG.api.listen_address_v6, CONFIG.api.api_port);
-        let sock_addr_v6 = bind_address_v6
-            .to_socket_addrs()?
-            .next()
-            .ok_or(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Could not resolve address",
-            ))?;
-        let listener_v6 = Async::<TcpListener>::bind(sock_addr_v6)?;
-        listeners.push(listener_v6);
-        println!(
-            "Server running on http://[{}]:{}",
-            sock_addr_v6.ip(),
-            sock_addr_v6.port()
+    let mut signals = Signals::new([Signal::Hup, Signal::Term, Signal::Int])?;
+    let config = config();
+
+    let mut cert_resolver = None;
+    let tls_acceptor = if config.tls.enabled() {
+        let resolver = Arc::new(
+            tls::ReloadingCertResolver::new(&config.tls.cert_path, &config.tls.key_path)
+                .map_err(io::Error::other)?,
         );
+        let client_verifier = if config.tls.client_ca_path.is_empty() {
+            None
+        } else {
+            Some(
+                tls::load_client_verifier(
+                    &config.tls.client_ca_path,
+                    config.tls.require_client_cert,
+                )
+                .map_err(io::Error::other)?,
+            )
+        };
+        cert_resolver = Some(resolver.clone());
+        Some(tls::build_acceptor(resolver, client_verifier).map_err(io::Error::other)?)
+    } else {
+        None
+    };
+    let scheme = if tls_acceptor.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+
+    let mut listeners = Vec::new();
+
+    // 由 systemd socket activation 传入监听 socket 时不再自行绑定地址
+    let activated = systemd::listen_fds()?;
+    if !activated.is_empty() {
+        for listener in activated {
+            let addr = listener.local_addr()?;
+            listeners.push(Async::new(listener)?);
+            println!(
+                "Server running on {}://{} (socket activation)",
+                scheme, addr
+            );
+        }
+    } else {
+        if !config.api.listen_address_v4.is_empty() {
+            let bind_address_v4 =
+                format!("{}:{}", config.api.listen_address_v4, config.api.api_port);
+            let sock_addr_v4 =
+                bind_address_v4
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Could not resolve address",
+                    ))?;
+            let listener_v4 = Async::<TcpListener>::bind(sock_addr_v4)?;
+            listeners.push(listener_v4);
+            println!(
+                "Server running on {}://{}:{}",
+                scheme,
+                sock_addr_v4.ip(),
+                sock_addr_v4.port()
+            );
+        }
+
+        if !config.api.listen_address_v6.is_empty() {
+            let bind_address_v6 =
+                format!("{}:{}", config.api.listen_address_v6, config.api.api_port);
+            let sock_addr_v6 =
+                bind_address_v6
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Could not resolve address",
+                    ))?;
+            let listener_v6 = Async::<TcpListener>::bind(sock_addr_v6)?;
+            listeners.push(listener_v6);
+            println!(
+                "Server running on {}://[{}]:{}",
+                scheme,
+                sock_addr_v6.ip(),
+                sock_addr_v6.port()
+            );
+        }
     }
 
+    let unix_listener = if config.unix_socket.path.is_empty() {
+        None
+    } else {
+        let listener = unix_socket::bind(&config.unix_socket).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to bind Unix socket {}: {}",
+                config.unix_socket.path, e
+            ))
+        })?;
+        println!("Server running on unix:{}", config.unix_socket.path);
+        Some(listener)
+    };
+
+    // TCP 与 Unix socket 共用连接数上限，达到上限后暂停 accept，新连接在内核队列中等待
+    let connection_slots = Arc::new(Semaphore::new(config.api.max_connections));
+
     smol::block_on(async {
+        let mut accept_tasks = Vec::new();
         for listener in listeners {
             let db_clone = db.clone();
-            smol::spawn(async move {
+            let tls_acceptor = tls_acceptor.clone();
+            let connection_slots = connection_slots.clone();
+            accept_tasks.push(smol::spawn(async move {
                 loop {
-                    let (stream, _) = match listener.accept().await {
+                    let slot = connection_slots.acquire_arc().await;
+                    let (stream, peer_addr) = match listener.accept().await {
                         Ok(conn) => conn,
                         Err(e) if e.kind() == io::ErrorKind::Interrupted => {
-                            // 忽略 ctrl-c 信号错误
+                            // 忽略信号导致的中断
                             continue;
                         }
                         Err(e) => {
@@ -204,29 +927,401 @@ fn main() -> std::io::Result<()> {
                         }
                     };
 
-                    let cloneable_stream = CloneableStream {
-                        inner: Arc::new(Mutex::new(stream)),
-                    };
+                    let db_for_handler = db_clone.clone();
+                    let tls_acceptor = tls_acceptor.clone();
+                    smol::spawn(async move {
+                        let _slot = slot;
+                        let result = match tls_acceptor {
+                            Some(acceptor) => match acceptor
+                                .accept(stream)
+                                .or(async {
+                                    // 握手同样受请求头超时限制，避免占住连接名额
+                                    smol::Timer::after(Duration::from_secs(
+                                        crate::config().api.header_timeout,
+                                    ))
+                                    .await;
+                                    Err(io::Error::new(
+                                        io::ErrorKind::TimedOut,
+                                        "handshake timed out",
+                                    ))
+                                })
+                                .await
+                            {
+                                Ok(tls_stream) => {
+                                    let identity = tls_stream
+                                        .get_ref()
+                                        .1
+                                        .peer_certificates()
+                                        .and_then(|certs| certs.first())
+                                        .and_then(tls::client_identity);
+                                    let auth = match identity {
+                                        Some(cn) => ConnectionAuth::ClientCertificate(
+                                            tls::ClientCertificate(cn),
+                                        ),
+                                        None => ConnectionAuth::None,
+                                    };
+                                    serve_connection(
+                                        tls_stream,
+                                        Some(peer_addr),
+                                        db_for_handler,
+                                        auth,
+                                    )
+                                    .await
+                                }
+                                Err(e) => {
+                                    eprintln!("TLS handshake with {} failed: {}", peer_addr, e);
+                                    return;
+                                }
+                            },
+                            None => {
+                                serve_connection(
+                                    stream,
+                                    Some(peer_addr),
+                                    db_for_handler,
+                                    ConnectionAuth::None,
+                                )
+                                .await
+                            }
+                        };
+                        if let Err(e) = result {
+                            eprintln!("Connection error: {}", e);
+                        }
+                    })
+                    .detach();
+                }
+            }));
+        }
 
+        if let Some(listener) = unix_listener {
+            let db_clone = db.clone();
+            let connection_slots = connection_slots.clone();
+            accept_tasks.push(smol::spawn(async move {
+                loop {
+                    let slot = connection_slots.acquire_arc().await;
+                    let stream = match listener.accept().await {
+                        Ok((stream, _)) => stream,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            eprintln!("Error accepting Unix socket connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let auth = match unix_socket::peer_credentials(stream.get_ref()) {
+                        Ok(cred) => ConnectionAuth::PeerCredentials(cred),
+                        Err(e) => {
+                            eprintln!("Failed to read peer credentials: {}", e);
+                            ConnectionAuth::None
+                        }
+                    };
                     let db_for_handler = db_clone.clone();
                     smol::spawn(async move {
-                        if let Err(e) = async_h1::server::accept(cloneable_stream, move |req| {
-                            serve_router(req, db_for_handler.clone())
-                        })
-                        .await
-                        {
+                        let _slot = slot;
+                        if let Err(e) = serve_connection(stream, None, db_for_handler, auth).await {
                             eprintln!("Connection error: {}", e);
                         }
                     })
                     .detach();
                 }
+            }));
+        }
+
+        if let Some(resolver) = cert_resolver {
+            smol::spawn(async move {
+                loop {
+                    smol::Timer::after(tls::CERT_RELOAD_INTERVAL).await;
+                    let resolver = resolver.clone();
+                    match smol::unblock(move || resolver.reload_if_changed()).await {
+                        Ok(true) => println!("TLS certificate reloaded"),
+                        Ok(false) => {}
+                        Err(e) => eprintln!("Failed to reload TLS certificate: {}", e),
+                    }
+                }
+            })
+            .detach();
+        }
+
+        let job_worker = smol::spawn(jobs::run_worker(db.clone()));
+
+        systemd::notify_or_log("READY=1\nSTATUS=Accepting requests");
+        if let Some(interval) = systemd::watchdog_interval() {
+            // 看门狗由事件循环驱动，事件循环卡死时 systemd 会重启服务
+            smol::spawn(async move {
+                loop {
+                    systemd::notify_or_log("WATCHDOG=1");
+                    smol::Timer::after(interval).await;
+                }
             })
             .detach();
         }
 
-        // Wait for Ctrl-C
-        ctrlc_receiver.recv().await.ok();
-        println!("\nCtrl-C received, shutting down...");
+        // SIGHUP 重新加载配置，SIGTERM/SIGINT 退出
+        loop {
+            match signals.next().await {
+                Some(Ok(Signal::Hup)) => {
+                    systemd::notify_or_log("RELOADING=1");
+                    smol::unblock(reload_config).await;
+                    systemd::notify_or_log("READY=1");
+                }
+                Some(Ok(signal)) => {
+                    println!("\n{} received, shutting down...", signal_name(signal));
+                    break;
+                }
+                Some(Err(e)) => eprintln!("Error receiving signal: {}", e),
+                None => break,
+            }
+        }
+
+        // 停止接受新连接，已建立连接上的新请求返回 503
+        shutdown::begin_shutdown();
+        systemd::notify_or_log("STOPPING=1\nSTATUS=Draining in-flight requests");
+        drop(accept_tasks);
+        if !config.unix_socket.path.is_empty() {
+            fs::remove_file(&config.unix_socket.path).ok();
+        }
+
+        // 使用重新加载后的值
+        let timeout = Duration::from_secs(crate::config().api.shutdown_timeout);
+        if shutdown::in_flight() > 0 {
+            println!(
+                "Waiting up to {}s for {} in-flight requests, send the signal again to exit immediately",
+                timeout.as_secs(),
+                shutdown::in_flight()
+            );
+        }
+        let drained = shutdown::drain(timeout)
+            .or(async {
+                while let Some(signal) = signals.next().await {
+                    if !matches!(signal, Ok(Signal::Hup)) {
+                        break;
+                    }
+                }
+                false
+            })
+            .await;
+        if !drained {
+            eprintln!(
+                "Exiting with {} requests still in flight",
+                shutdown::in_flight()
+            );
+        }
+
+        drop(job_worker);
+
+        if let Err(e) = shutdown::flush_db(db, Duration::from_secs(5)).await {
+            eprintln!("Failed to flush database: {}", e);
+        }
+        println!("Shutdown complete");
         Ok(())
     })
 }
+
+fn signal_name(signal: Signal) -> &'static str {
+    match signal {
+        Signal::Hup => "SIGHUP",
+        Signal::Term => "SIGTERM",
+        Signal::Int => "SIGINT",
+        _ => "Signal",
+    }
+}
+
+// 连接建立时确定的身份信息，附加到该连接的每个请求上，由 serve_router 决定是否信任
+#[derive(Clone)]
+enum ConnectionAuth {
+    None,
+    ClientCertificate(tls::ClientCertificate),
+    PeerCredentials(unix_socket::PeerCredentials),
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    peer_addr: Option<SocketAddr>,
+    db: Db,
+    auth: ConnectionAuth,
+) -> http_types::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    // 每个连接开始时读取一次限制，SIGHUP 后对新连接生效
+    let limits = config().api.connection_limits();
+    connection::serve(stream, limits, move |mut req| {
+        req.set_peer_addr(peer_addr);
+        match &auth {
+            ConnectionAuth::None => {}
+            ConnectionAuth::ClientCertificate(cert) => {
+                req.ext_mut().insert(cert.clone());
+            }
+            ConnectionAuth::PeerCredentials(cred) => {
+                req.ext_mut().insert(*cred);
+            }
+        }
+        serve_request(req, db.clone())
+    })
+    .await
+}
+
+// 统计进行中的请求，关闭过程中拒绝新请求
+async fn serve_request(req: Request, db: Db) -> http_types::Result<Response> {
+    let Some(_guard) = shutdown::InFlightGuard::enter() else {
+        let mut res = Response::new(StatusCode::ServiceUnavailable);
+        res.insert_header("Connection", "close");
+        res.set_body("Server is shutting down");
+        return Ok(res);
+    };
+    serve_router(req, db).await
+}
+
+fn main() -> ExitCode {
+    match cli::run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[API]
+api_port = 4242
+listen_address_v4 = "127.0.0.1"
+listen_address_v6 = "::1"
+secret = "abcdefghijklmnopqrstuvwxyz1234567890"
+
+[Peer]
+link_local = "fe80::253"
+wireguard_private_key = "your_private_key_here"
+port_prefix_number = 2
+
+[Environment]
+init_system = "systemd"
+systemctl_path = "/usr/bin/systemctl"
+rc_service_path = "/sbin/rc-service"
+rc_update_path = "/sbin/rc-update"
+birdc_path = "/usr/sbin/birdc"
+"#;
+
+    fn test_config() -> AppConfig {
+        toml::from_str(CONFIG).unwrap()
+    }
+
+    #[test]
+    fn test_reload_changes() {
+        let old = test_config();
+        let mut new = test_config();
+        assert!(rejected_reload_changes(&old, &new).is_empty());
+        assert!(restart_required_changes(&old, &new).is_empty());
+        assert!(resync_required_changes(&old, &new).is_empty());
+        assert!(deferred_reload_changes(&old, &new).is_empty());
+
+        new.api.secret = "changed".to_string();
+        new.api.listen_address_v6 = String::new();
+        new.peer.allowed_ips.push("10.127.0.0/16".to_string());
+        assert_eq!(
+            restart_required_changes(&old, &new),
+            vec!["API.listen_address_v6"]
+        );
+        assert_eq!(
+            resync_required_changes(&old, &new),
+            vec!["Peer.allowed_ips"]
+        );
+        assert!(rejected_reload_changes(&old, &new).is_empty());
+
+        new.api.shutdown_timeout = 5;
+        new.api.idle_timeout = 5;
+        new.paths.bird_peers_dir = "/etc/bird/dn42".to_string();
+        let deferred: Vec<&str> = deferred_reload_changes(&old, &new)
+            .into_iter()
+            .map(|(setting, _)| setting)
+            .collect();
+        assert_eq!(
+            deferred,
+            vec![
+                "Paths.wireguard_dir/bird_peers_dir",
+                "API.shutdown_timeout",
+                "API.max_body_size/header_timeout/read_timeout/idle_timeout"
+            ]
+        );
+
+        new.peer.interface_prefix = "wg_".to_string();
+        new.peer.port_prefix_number = 3;
+        new.env.init_system = "openrc".to_string();
+        assert_eq!(
+            rejected_reload_changes(&old, &new),
+            vec![
+                "Peer.interface_prefix",
+                "Peer.port_prefix_number",
+                "Environment.init_system"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_value_problems() {
+        let mut config = test_config();
+        config.peer.wireguard_private_key =
+            "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=".to_string();
+        assert!(config_value_problems(&config).is_empty());
+
+        config.peer.wireguard_private_key = "not a key".to_string();
+        config.peer.link_local = "fe80::1/64".to_string();
+        config.peer.allowed_ips = vec!["10.0.0.0/8".to_string(), "10.0.0.0".to_string()];
+        config.env.init_system = "runit".to_string();
+        let problems = config_value_problems(&config);
+        assert_eq!(problems.len(), 4);
+        assert!(problems[0].starts_with("Peer.link_local"));
+        assert!(problems[1].starts_with("Peer.wireguard_private_key"));
+        assert!(problems[2].contains("10.0.0.0"));
+        assert!(problems[3].starts_with("Environment.init_system"));
+    }
+
+    #[test]
+    fn test_tls_config_problems() {
+        let mut config = test_config();
+        config.peer.wireguard_private_key =
+            "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=".to_string();
+        config.tls.cert_path = "/etc/ssl/api.pem".to_string();
+        let problems = config_value_problems(&config);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("cert_path and key_path"));
+
+        config.tls = TlsConfig {
+            require_client_cert: true,
+            ..Default::default()
+        };
+        let problems = config_value_problems(&config);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("TLS.require_client_cert"));
+    }
+
+    #[test]
+    fn test_rate_limit_config_problems() {
+        let mut config = test_config();
+        config.peer.wireguard_private_key =
+            "YNqHbfBQKaGvzefSSuufuNKrJs9UCUP9QG8bmr4+M3s=".to_string();
+        config.rate_limit.per_ip_rate = -1.0;
+        config.rate_limit.per_token_burst = 0;
+        config.rate_limit.lockout_seconds = 0;
+        let problems = config_value_problems(&config);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("RateLimit.per_ip_rate"));
+        assert!(problems[1].starts_with("RateLimit.per_token_burst"));
+        assert!(problems[2].starts_with("RateLimit.lockout_seconds"));
+
+        config.rate_limit.per_ip_rate = 0.0;
+        config.rate_limit.per_token_rate = 0.0;
+        config.rate_limit.max_failed_auth = 0;
+        assert!(config_value_problems(&config).is_empty());
+    }
+
+    #[test]
+    fn test_check_executable() {
+        assert!(check_executable("sh", "/bin/sh").is_none());
+        assert!(check_executable("dir", "/").is_some());
+        assert!(check_executable("missing", "/nonexistent/birdc").is_some());
+        assert!(check_executable("empty", "").is_some());
+    }
+}
@@ -1,11 +1,49 @@
-use crate::CONFIG;
+use crate::config;
 use anyhow::Result;
 use askama::Template;
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerState {
+    Active,
+    Disabled,
+    // waiting for an admin to approve, nothing has been deployed yet
+    Pending,
+    Rejected,
+}
+
+impl PeerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerState::Active => "active",
+            PeerState::Disabled => "disabled",
+            PeerState::Pending => "pending",
+            PeerState::Rejected => "rejected",
+        }
+    }
+
+    // 是否已生成配置文件并部署到系统中
+    pub fn is_deployed(&self) -> bool {
+        matches!(self, PeerState::Active | PeerState::Disabled)
+    }
+
+    pub fn parse(s: &str) -> Option<PeerState> {
+        match s {
+            "active" => Some(PeerState::Active),
+            "disabled" => Some(PeerState::Disabled),
+            "pending" => Some(PeerState::Pending),
+            "rejected" => Some(PeerState::Rejected),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
 pub struct PeerDbInfo {
     pub asn: u64,
     pub wireguard_endpoint: String,
@@ -14,9 +52,38 @@ pub struct PeerDbInfo {
     pub interface_name: String,
     pub wireguard_config_path: String,
     pub bird_config_path: String,
+    pub state: PeerState,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub reject_reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persistent_keepalive: Option<u16>,
+    pub extra_allowed_ips: Vec<String>,
+    pub contact: String,
+    pub notes: String,
+    // Unix timestamps in seconds, None for peers created before they were tracked
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl From<PeerDbInfo> for Peer {
+    fn from(info: PeerDbInfo) -> Self {
+        Peer {
+            asn: info.asn,
+            wireguard_endpoint: info.wireguard_endpoint,
+            wireguard_link_local: info.wireguard_link_local,
+            wireguard_public_key: info.wireguard_public_key,
+            mtu: info.mtu,
+            persistent_keepalive: info.persistent_keepalive,
+            extra_allowed_ips: info.extra_allowed_ips,
+            contact: info.contact,
+            notes: info.notes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
 pub struct Peer {
     pub asn: u64,
     #[serde(default)]
@@ -25,24 +92,181 @@ pub struct Peer {
     pub wireguard_link_local: String,
     #[serde(default)]
     pub wireguard_public_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistent_keepalive: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_allowed_ips: Vec<String>,
+    // e-mail, IRC or Telegram handle of the peer operator
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub contact: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+}
+
+// 区分字段缺失（不修改）与显式 null（清除）
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// /update 请求体，除 asn 外缺失的字段保持不变
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct PeerUpdate {
+    pub asn: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wireguard_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wireguard_link_local: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wireguard_public_key: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mtu: Option<Option<u16>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub persistent_keepalive: Option<Option<u16>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_allowed_ips: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+// 以完整的 peer 替换所有字段，用于 PUT /v1/peers/{asn}
+impl From<Peer> for PeerUpdate {
+    fn from(peer: Peer) -> Self {
+        PeerUpdate {
+            asn: peer.asn,
+            wireguard_endpoint: Some(peer.wireguard_endpoint),
+            wireguard_link_local: Some(peer.wireguard_link_local),
+            wireguard_public_key: Some(peer.wireguard_public_key),
+            mtu: Some(peer.mtu),
+            persistent_keepalive: Some(peer.persistent_keepalive),
+            extra_allowed_ips: Some(peer.extra_allowed_ips),
+            contact: Some(peer.contact),
+            notes: Some(peer.notes),
+        }
+    }
+}
+
+impl PeerUpdate {
+    pub fn apply_to(self, peer: &mut Peer) {
+        if let Some(endpoint) = self.wireguard_endpoint {
+            peer.wireguard_endpoint = endpoint;
+        }
+        if let Some(link_local) = self.wireguard_link_local {
+            peer.wireguard_link_local = link_local;
+        }
+        if let Some(public_key) = self.wireguard_public_key {
+            peer.wireguard_public_key = public_key;
+        }
+        if let Some(mtu) = self.mtu {
+            peer.mtu = mtu;
+        }
+        if let Some(keepalive) = self.persistent_keepalive {
+            peer.persistent_keepalive = keepalive;
+        }
+        if let Some(extra_allowed_ips) = self.extra_allowed_ips {
+            peer.extra_allowed_ips = extra_allowed_ips;
+        }
+        if let Some(contact) = self.contact {
+            peer.contact = contact;
+        }
+        if let Some(notes) = self.notes {
+            peer.notes = notes;
+        }
+    }
+}
+
+// Linux 接口名最长 15 字节，需为 4 位 ASN 后缀留出空间
+pub const MAX_INTERFACE_PREFIX_LENGTH: usize = 11;
+
+pub const MAX_EXTRA_ALLOWED_IPS: usize = 32;
+pub const MAX_CONTACT_LENGTH: usize = 256;
+pub const MAX_NOTES_LENGTH: usize = 4096;
+
+// 校验 `地址/前缀长度` 形式的 CIDR，例如 `172.20.0.0/14` 或 `fd00::/8`
+pub fn is_valid_cidr(cidr: &str) -> bool {
+    let Some((addr, prefix)) = cidr.split_once('/') else {
+        return false;
+    };
+    let Ok(prefix) = prefix.parse::<u8>() else {
+        return false;
+    };
+    match addr.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(_)) => prefix <= 32,
+        Ok(std::net::IpAddr::V6(_)) => prefix <= 128,
+        Err(_) => false,
+    }
+}
+
+// 校验不带前缀长度的 IPv6 链路本地地址 (fe80::/10)
+pub fn is_valid_link_local(addr: &str) -> bool {
+    match addr.parse::<std::net::Ipv6Addr>() {
+        Ok(addr) => {
+            let seg0 = addr.segments()[0];
+            (seg0 & 0xFFC0) == 0xFE80
+        }
+        Err(_) => false,
+    }
+}
+
+// 校验 base64 编码的 32 字节 WireGuard 公钥或私钥
+pub fn is_valid_wireguard_key(key: &str) -> bool {
+    if key.len() != 44 {
+        return false;
+    }
+    match BASE64_STANDARD.decode(key) {
+        Ok(bytes) => bytes.len() == 32,
+        Err(_) => false,
+    }
+}
+
+pub fn is_valid_interface_prefix(prefix: &str) -> bool {
+    !prefix.is_empty()
+        && prefix.len() <= MAX_INTERFACE_PREFIX_LENGTH
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
 }
 
 impl Peer {
     pub fn gen_interface_name(&self) -> String {
-        format!("dn42_{:04}", self.asn % 10000)
+        format!("{}{:04}", config().peer.interface_prefix, self.asn % 10000)
     }
 
     pub fn gen_wireguard_config_path(&self) -> String {
-        format!("/etc/wireguard/{}.conf", self.gen_interface_name())
+        format!(
+            "{}/{}.conf",
+            config().paths.wireguard_dir.trim_end_matches('/'),
+            self.gen_interface_name()
+        )
     }
 
     pub fn gen_bird_config_path(&self) -> String {
-        format!("/etc/bird/peers/{}.conf", self.gen_interface_name())
+        format!(
+            "{}/{}.conf",
+            config().paths.bird_peers_dir.trim_end_matches('/'),
+            self.gen_interface_name()
+        )
     }
 
+    #[allow(clippy::manual_range_contains)]
     pub fn gen_listen_port(&self) -> Result<u16> {
         let asn_suffix = self.asn % 10000;
-        let port_prefix = CONFIG.peer.port_prefix_number as u64;
+        let port_prefix = config().peer.port_prefix_number as u64;
         let combined = port_prefix * 10000 + asn_suffix;
         if combined > 65535 || combined < 1024 {
             Err(anyhow::anyhow!("Invalid port number"))
@@ -51,6 +275,7 @@ impl Peer {
         }
     }
 
+    #[allow(clippy::match_like_matches_macro)]
     pub fn is_valid_wireguard_endpoint(&self) -> bool {
         let wg_url = format!("wg://{}", self.wireguard_endpoint);
         let Ok(u) = Url::parse(&wg_url) else {
@@ -67,13 +292,7 @@ impl Peer {
     }
 
     pub fn is_valid_link_local(&self) -> bool {
-        match self.wireguard_link_local.parse::<std::net::Ipv6Addr>() {
-            Ok(addr) => {
-                let seg0 = addr.segments()[0];
-                (seg0 & 0xFFC0) == 0xFE80
-            }
-            Err(_) => false,
-        }
+        is_valid_link_local(&self.wireguard_link_local)
     }
 
     pub fn wireguard_link_local_strip_cidr(&mut self) {
@@ -87,14 +306,347 @@ impl Peer {
     }
 
     pub fn is_valid_wireguard_public_key(&self) -> bool {
-        if self.wireguard_public_key.len() != 44 {
-            return false;
+        is_valid_wireguard_key(&self.wireguard_public_key)
+    }
+
+    pub fn is_valid_mtu(&self) -> bool {
+        match self.mtu {
+            Some(mtu) => (1280..=1500).contains(&mtu),
+            None => true,
         }
-        match BASE64_STANDARD.decode(&self.wireguard_public_key) {
-            Ok(bytes) => bytes.len() == 32,
-            Err(_) => false,
+    }
+
+    pub fn is_valid_persistent_keepalive(&self) -> bool {
+        match self.persistent_keepalive {
+            Some(interval) => (1..=3600).contains(&interval),
+            None => true,
         }
     }
+
+    pub fn is_valid_extra_allowed_ips(&self) -> bool {
+        self.extra_allowed_ips.len() <= MAX_EXTRA_ALLOWED_IPS
+            && self
+                .extra_allowed_ips
+                .iter()
+                .all(|cidr| is_valid_cidr(cidr))
+    }
+
+    pub fn is_valid_contact(&self) -> bool {
+        self.contact.len() <= MAX_CONTACT_LENGTH && !self.contact.chars().any(char::is_control)
+    }
+
+    pub fn is_valid_notes(&self) -> bool {
+        self.notes.len() <= MAX_NOTES_LENGTH
+    }
+
+    // 依次检查所有字段，返回第一个错误；会先去掉 link-local 地址的前缀长度
+    pub fn validate(&mut self) -> std::result::Result<(), String> {
+        if !self.is_valid_wireguard_endpoint() {
+            return Err("Invalid Wireguard endpoint".to_string());
+        }
+        if !self.is_valid_asn() {
+            return Err("Invalid ASN".to_string());
+        }
+        self.wireguard_link_local_strip_cidr();
+        if !self.is_valid_link_local() {
+            return Err("Invalid Link-Local address".to_string());
+        }
+        if !self.is_valid_wireguard_public_key() {
+            return Err("Invalid Wireguard public key".to_string());
+        }
+        if !self.is_valid_mtu() {
+            return Err("Invalid MTU, must be between 1280 and 1500".to_string());
+        }
+        if !self.is_valid_persistent_keepalive() {
+            return Err("Invalid persistent keepalive, must be between 1 and 3600".to_string());
+        }
+        if !self.is_valid_extra_allowed_ips() {
+            return Err("Invalid extra allowed IPs".to_string());
+        }
+        if !self.is_valid_contact() {
+            return Err(format!(
+                "Invalid contact, must be at most {} characters on a single line",
+                MAX_CONTACT_LENGTH
+            ));
+        }
+        if !self.is_valid_notes() {
+            return Err(format!(
+                "Invalid notes, must be at most {} bytes",
+                MAX_NOTES_LENGTH
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RejectRequest {
+    pub asn: u64,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ListRequest {
+    #[serde(default)]
+    pub state: Option<PeerState>,
+}
+
+fn default_exported_state() -> PeerState {
+    PeerState::Active
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportedPeer {
+    #[serde(flatten)]
+    pub peer: Peer,
+    #[serde(default = "default_exported_state")]
+    pub state: PeerState,
+}
+
+// /export 输出与 /import 输入的文档格式
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PeerExport {
+    #[serde(default)]
+    pub peers: Vec<ExportedPeer>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportResult {
+    pub asn: u64,
+    // added, pending, disabled, would_add, skipped or failed
+    pub outcome: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<ImportResult>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    Write,
+    Delete,
+    Unchanged,
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Eq)]
+pub struct PlannedFile {
+    pub path: String,
+    pub action: FileAction,
+    // 渲染出的完整内容，删除时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+// dry run 的结果：通过校验后将写入或删除的文件与将执行的命令，实际不做任何修改
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Eq)]
+pub struct DryRunPlan {
+    pub dry_run: bool,
+    pub action: String,
+    pub asn: u64,
+    // 操作完成后 peer 的状态，del 时为删除前的状态
+    pub state: PeerState,
+    pub interface_name: String,
+    pub listen_port: u16,
+    pub files: Vec<PlannedFile>,
+    pub commands: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AdoptResult {
+    // WireGuard or BIRD file the result refers to
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface_name: Option<String>,
+    // adopted, would_adopt, already_managed or unmapped
+    pub outcome: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AdoptReport {
+    pub dry_run: bool,
+    pub results: Vec<AdoptResult>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResyncResult {
+    pub asn: u64,
+    pub interface_name: String,
+    pub changed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobAction {
+    Add,
+    Update,
+    Del,
+    // PUT /v1/peers/{asn}: create or replace
+    Put,
+}
+
+impl JobAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobAction::Add => "add",
+            JobAction::Update => "update",
+            JobAction::Del => "del",
+            JobAction::Put => "put",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<JobAction> {
+        match s {
+            "add" => Some(JobAction::Add),
+            "update" => Some(JobAction::Update),
+            "del" => Some(JobAction::Del),
+            "put" => Some(JobAction::Put),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<JobStatus> {
+        match s {
+            "queued" => Some(JobStatus::Queued),
+            "running" => Some(JobStatus::Running),
+            "succeeded" => Some(JobStatus::Succeeded),
+            "failed" => Some(JobStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct JobLogEntry {
+    pub timestamp: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub action: JobAction,
+    pub asn: Option<u64>,
+    pub status: JobStatus,
+    // HTTP status and body the synchronous request would have returned
+    pub result_status: Option<u16>,
+    pub result: String,
+    pub principal: String,
+    // request body, only used by the worker
+    #[serde(skip)]
+    pub payload: String,
+    #[serde(skip)]
+    pub source_ip: Option<String>,
+    // Unix timestamps in seconds
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub log: Vec<JobLogEntry>,
+}
+
+// 异步请求入队后的 202 响应体
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct JobAccepted {
+    pub job_id: i64,
+    pub status: JobStatus,
+    pub location: String,
+}
+
+// /v1 接口的错误响应体
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct ApiError {
+    pub error: String,
+}
+
+// 按 Idempotency-Key 保存的响应，重复请求时原样返回
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub location: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IdempotencyClaim {
+    // 首次使用该 key，由当前请求执行
+    Claimed,
+    // 使用同一个 key 的请求仍在处理中
+    InProgress,
+    // 同一个 key 已用于内容不同的请求
+    Mismatch,
+    Completed(StoredResponse),
+}
+
+// 已通过认证的请求方，记录于审计日志
+#[derive(Clone, Debug)]
+pub struct Principal(pub String);
+
+#[derive(Serialize, Debug)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: i64,
+    pub action: String,
+    pub asn: Option<u64>,
+    pub principal: String,
+    pub source_ip: Option<String>,
+    pub payload: serde_json::Value,
+    pub status: u16,
+    pub outcome: String,
+}
+
+pub const DEFAULT_AUDIT_LIMIT: u32 = 100;
+
+fn default_audit_limit() -> u32 {
+    DEFAULT_AUDIT_LIMIT
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub asn: Option<u64>,
+    // Unix timestamps in seconds, inclusive
+    #[serde(default)]
+    pub since: Option<i64>,
+    #[serde(default)]
+    pub until: Option<i64>,
+    #[serde(default = "default_audit_limit")]
+    pub limit: u32,
 }
 
 #[derive(Template)]
@@ -105,6 +657,9 @@ pub struct WireguardConfig {
     pub wireguard_link_local_ipv6: String,
     pub wireguard_peer_public_key: String,
     pub wireguard_peer_endpoint: String,
+    pub wireguard_allowed_ips: String,
+    pub wireguard_mtu: Option<u16>,
+    pub wireguard_persistent_keepalive: Option<u16>,
 }
 
 #[derive(Template)]
@@ -114,6 +669,7 @@ pub struct BirdConfig {
     pub wireguard_link_local_ipv6: String,
     pub peer_link_local_ipv6: String,
     pub peer_asn: u64,
+    pub disabled: bool,
 }
 
 #[cfg(test)]
@@ -126,6 +682,11 @@ mod tests {
             wireguard_endpoint: "1.2.3.4:51820".to_string(),
             wireguard_link_local: "fe80::1".to_string(),
             wireguard_public_key: "test".to_string(),
+            mtu: None,
+            persistent_keepalive: None,
+            extra_allowed_ips: Vec::new(),
+            contact: String::new(),
+            notes: String::new(),
         }
     }
 
@@ -175,6 +736,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::inconsistent_digit_grouping)]
     fn test_asn_invalid() {
         let mut peer = p();
         peer.asn = 4_242_420_00; // 9 digits
@@ -229,6 +791,148 @@ mod tests {
         assert!(!peer.is_valid_wireguard_public_key());
     }
 
+    #[test]
+    fn test_mtu_and_keepalive() {
+        let mut peer = p();
+        assert!(peer.is_valid_mtu());
+        assert!(peer.is_valid_persistent_keepalive());
+        peer.mtu = Some(1420);
+        assert!(peer.is_valid_mtu());
+        peer.mtu = Some(1279);
+        assert!(!peer.is_valid_mtu());
+        peer.mtu = Some(9000);
+        assert!(!peer.is_valid_mtu());
+        peer.persistent_keepalive = Some(25);
+        assert!(peer.is_valid_persistent_keepalive());
+        peer.persistent_keepalive = Some(0);
+        assert!(!peer.is_valid_persistent_keepalive());
+    }
+
+    #[test]
+    fn test_extra_allowed_ips() {
+        let mut peer = p();
+        assert!(peer.is_valid_extra_allowed_ips());
+        peer.extra_allowed_ips = vec!["10.127.0.0/16".to_string(), "fd10::/8".to_string()];
+        assert!(peer.is_valid_extra_allowed_ips());
+        peer.extra_allowed_ips = vec!["10.127.0.0".to_string()];
+        assert!(!peer.is_valid_extra_allowed_ips());
+        peer.extra_allowed_ips = vec!["10.127.0.0/33".to_string()];
+        assert!(!peer.is_valid_extra_allowed_ips());
+        peer.extra_allowed_ips = vec!["fd10::/129".to_string()];
+        assert!(!peer.is_valid_extra_allowed_ips());
+        peer.extra_allowed_ips = vec!["10.0.0.0/8".to_string(); MAX_EXTRA_ALLOWED_IPS + 1];
+        assert!(!peer.is_valid_extra_allowed_ips());
+    }
+
+    #[test]
+    fn test_interface_prefix() {
+        assert!(is_valid_interface_prefix("dn42_"));
+        assert!(is_valid_interface_prefix("wg-dn42"));
+        assert!(!is_valid_interface_prefix(""));
+        assert!(!is_valid_interface_prefix("dn42 "));
+        assert!(!is_valid_interface_prefix("dn42/"));
+        assert!(!is_valid_interface_prefix(
+            &"a".repeat(MAX_INTERFACE_PREFIX_LENGTH + 1)
+        ));
+    }
+
+    #[test]
+    fn test_contact_and_notes() {
+        let mut peer = p();
+        peer.contact = "noc@example.com".to_string();
+        assert!(peer.is_valid_contact());
+        peer.contact = "a\nb".to_string();
+        assert!(!peer.is_valid_contact());
+        peer.contact = "a".repeat(MAX_CONTACT_LENGTH + 1);
+        assert!(!peer.is_valid_contact());
+        peer.notes = "multi\nline".to_string();
+        assert!(peer.is_valid_notes());
+        peer.notes = "a".repeat(MAX_NOTES_LENGTH + 1);
+        assert!(!peer.is_valid_notes());
+    }
+
+    #[test]
+    fn test_update_distinguishes_missing_and_null() {
+        let mut peer = p();
+        peer.mtu = Some(1400);
+        peer.persistent_keepalive = Some(25);
+        let update: PeerUpdate =
+            serde_json::from_value(json!({"asn": 0, "mtu": null, "notes": "hello"})).unwrap();
+        update.apply_to(&mut peer);
+        assert_eq!(peer.mtu, None);
+        assert_eq!(peer.persistent_keepalive, Some(25));
+        assert_eq!(peer.notes, "hello");
+        assert_eq!(peer.wireguard_endpoint, "1.2.3.4:51820");
+    }
+
+    #[test]
+    fn test_render_wireguard_optional_fields() {
+        let mut config = WireguardConfig {
+            wireguard_private_key: "key".to_string(),
+            wireguard_listen_port: 20253,
+            wireguard_link_local_ipv6: "fe80::253".to_string(),
+            wireguard_peer_public_key: "peer".to_string(),
+            wireguard_peer_endpoint: "1.2.3.4:51820".to_string(),
+            wireguard_allowed_ips: "fd00::/8, fe80::/64".to_string(),
+            wireguard_mtu: None,
+            wireguard_persistent_keepalive: None,
+        };
+        let rendered = config.render().unwrap();
+        assert!(rendered.contains("ListenPort = 20253\nTable = off"));
+        assert!(!rendered.contains("MTU"));
+        assert!(!rendered.contains("PersistentKeepalive"));
+        assert!(rendered.contains("AllowedIPs = fd00::/8, fe80::/64"));
+
+        config.wireguard_mtu = Some(1400);
+        config.wireguard_persistent_keepalive = Some(25);
+        let rendered = config.render().unwrap();
+        assert!(rendered.contains("ListenPort = 20253\nMTU = 1400\nTable = off"));
+        assert!(rendered.contains("fe80::/64\nPersistentKeepalive = 25"));
+    }
+
+    #[test]
+    fn test_render_bird_disabled() {
+        let mut config = BirdConfig {
+            interface_name: "dn42_0253".to_string(),
+            wireguard_link_local_ipv6: "fe80::253".to_string(),
+            peer_link_local_ipv6: "fe80::1".to_string(),
+            peer_asn: 4242420253,
+            disabled: false,
+        };
+        assert!(!config.render().unwrap().contains("disabled;"));
+        config.disabled = true;
+        assert!(
+            config
+                .render()
+                .unwrap()
+                .contains("as 4242420253;\n    disabled;\n    ipv4 {")
+        );
+    }
+
+    #[test]
+    fn test_export_roundtrip_toml() {
+        let mut peer = p();
+        peer.asn = 4242420253;
+        peer.mtu = Some(1400);
+        peer.extra_allowed_ips = vec!["10.127.0.0/16".to_string()];
+        let export = PeerExport {
+            peers: vec![ExportedPeer {
+                peer,
+                state: PeerState::Disabled,
+            }],
+        };
+        let text = toml::to_string(&export).unwrap();
+        let parsed: PeerExport = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.peers.len(), 1);
+        assert_eq!(parsed.peers[0].peer.asn, 4242420253);
+        assert_eq!(parsed.peers[0].peer.mtu, Some(1400));
+        assert_eq!(parsed.peers[0].state, PeerState::Disabled);
+
+        let parsed: PeerExport =
+            serde_json::from_value(json!({"peers": [{"asn": 4242420253u64}]})).unwrap();
+        assert_eq!(parsed.peers[0].state, PeerState::Active);
+    }
+
     #[test]
     fn test_deserialize_only_asn() {
         let v = json!({"asn": 4242420000u64});
@@ -237,6 +941,9 @@ mod tests {
         assert_eq!(peer.wireguard_endpoint, "");
         assert_eq!(peer.wireguard_link_local, "");
         assert_eq!(peer.wireguard_public_key, "");
+        assert_eq!(peer.mtu, None);
+        assert_eq!(peer.persistent_keepalive, None);
+        assert!(peer.extra_allowed_ips.is_empty());
     }
 
     #[test]
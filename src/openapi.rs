@@ -0,0 +1,308 @@
+use crate::model::{
+    ApiError, DryRunPlan, Job, JobAccepted, Peer, PeerDbInfo, PeerState, PeerUpdate,
+};
+use schemars::generate::SchemaSettings;
+use serde_json::{Map, Value, json};
+
+fn json_content(schema: &Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn response(description: &str, schema: &Value) -> Value {
+    json!({ "description": description, "content": json_content(schema) })
+}
+
+// 各接口共有的错误响应
+fn with_common_responses(mut responses: Map<String, Value>, error: &Value) -> Value {
+    responses.insert(
+        "401".to_string(),
+        json!({ "description": "Missing or invalid bearer secret" }),
+    );
+    responses.insert(
+        "429".to_string(),
+        json!({
+            "description": "Rate limited or locked out after failed authentications",
+            "headers": { "Retry-After": { "schema": { "type": "integer" } } }
+        }),
+    );
+    responses.insert(
+        "500".to_string(),
+        response("Database or system error", error),
+    );
+    Value::Object(responses)
+}
+
+fn responses(entries: &[(&str, Value)], error: &Value) -> Value {
+    let map = entries
+        .iter()
+        .map(|(status, value)| (status.to_string(), value.clone()))
+        .collect();
+    with_common_responses(map, error)
+}
+
+// 从 model.rs 中请求与响应类型生成 /v1 接口的 OpenAPI 文档
+pub fn openapi_document() -> Value {
+    // 请求体按反序列化规则生成，响应体按序列化规则生成
+    let mut requests = SchemaSettings::openapi3()
+        .for_deserialize()
+        .into_generator();
+    let peer = json!(requests.subschema_for::<Peer>());
+    let peer_update = json!(requests.subschema_for::<PeerUpdate>());
+    let state = json!(requests.subschema_for::<PeerState>());
+    let asn = json!(requests.subschema_for::<u64>());
+    let mut serialized = SchemaSettings::openapi3().for_serialize().into_generator();
+    let peer_info = json!(serialized.subschema_for::<PeerDbInfo>());
+    let error = json!(serialized.subschema_for::<ApiError>());
+    let job = json!(serialized.subschema_for::<Job>());
+    let job_accepted = json!(serialized.subschema_for::<JobAccepted>());
+    let plan = json!(serialized.subschema_for::<DryRunPlan>());
+    let peer_list = json!({ "type": "array", "items": peer_info });
+
+    let mut schemas = requests.take_definitions(true);
+    schemas.extend(serialized.take_definitions(true));
+    // asn 取自路径，请求体中可省略，提供时必须与路径一致
+    for name in ["Peer", "PeerUpdate"] {
+        if let Some(Value::Array(required)) = schemas
+            .get_mut(name)
+            .and_then(|schema| schema.get_mut("required"))
+        {
+            required.retain(|field| field != "asn");
+        }
+    }
+
+    let asn_param = json!({
+        "name": "asn",
+        "in": "path",
+        "required": true,
+        "schema": asn,
+    });
+    let job_id = json!(requests.subschema_for::<i64>());
+    let wait_param = json!({
+        "name": "wait",
+        "in": "query",
+        "required": false,
+        "description": "true runs the change before responding and returns its result; otherwise the change is queued as a job and the response is 202 with the job id",
+        "schema": { "type": "boolean" },
+    });
+    let idempotency_param = json!({
+        "name": "Idempotency-Key",
+        "in": "header",
+        "required": false,
+        "description": "Repeating the request with the same key within 24 hours replays the stored response instead of running it again",
+        "schema": { "type": "string", "maxLength": 255 },
+    });
+    let dry_run_param = json!({
+        "name": "dry_run",
+        "in": "query",
+        "required": false,
+        "description": "true validates the request and returns the files and commands it would write and run, without changing anything",
+        "schema": { "type": "boolean" },
+    });
+    let job_queued = response("Queued as a job (without wait=true)", &job_accepted);
+    let not_found = response("Peer not found", &error);
+    let bad_request = response("Invalid request body or peer", &error);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "dn42-autopeer-daemon",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "security": [{ "bearer": [] }],
+        "paths": {
+            "/v1/peers": {
+                "get": {
+                    "summary": "List peers",
+                    "operationId": "listPeers",
+                    "parameters": [{
+                        "name": "state",
+                        "in": "query",
+                        "required": false,
+                        "schema": state,
+                    }],
+                    "responses": responses(&[
+                        ("200", response("All peers, ordered by ASN", &peer_list)),
+                        ("400", response("Invalid state", &error)),
+                    ], &error),
+                }
+            },
+            "/v1/peers/{asn}": {
+                "parameters": [asn_param],
+                "get": {
+                    "summary": "Get a peer",
+                    "operationId": "getPeer",
+                    "responses": responses(&[
+                        ("200", response("The peer", &peer_info)),
+                        ("404", not_found.clone()),
+                    ], &error),
+                },
+                "put": {
+                    "summary": "Create a peer, or replace all fields of an existing one",
+                    "operationId": "putPeer",
+                    "parameters": [wait_param, idempotency_param, dry_run_param],
+                    "requestBody": { "required": true, "content": json_content(&peer) },
+                    "responses": responses(&[
+                        ("200", json!({
+                            "description": "Existing peer replaced, or the plan with dry_run=true",
+                            "content": json_content(&json!({ "oneOf": [peer_info, plan] })),
+                        })),
+                        ("201", response("Peer created and deployed", &peer_info)),
+                        ("202", json!({
+                            "description": "Queued as a job (without wait=true), or peer created and pending approval",
+                            "content": json_content(&json!({ "oneOf": [peer_info, job_accepted] })),
+                        })),
+                        ("400", bad_request.clone()),
+                    ], &error),
+                },
+                "patch": {
+                    "summary": "Change some fields of a peer, null clears mtu and persistent_keepalive",
+                    "operationId": "patchPeer",
+                    "parameters": [wait_param, idempotency_param, dry_run_param],
+                    "requestBody": { "required": true, "content": json_content(&peer_update) },
+                    "responses": responses(&[
+                        ("200", json!({
+                            "description": "The updated peer, or the plan with dry_run=true",
+                            "content": json_content(&json!({ "oneOf": [peer_info, plan] })),
+                        })),
+                        ("202", job_queued.clone()),
+                        ("400", bad_request),
+                        ("404", not_found.clone()),
+                    ], &error),
+                },
+                "delete": {
+                    "summary": "Remove a peer and its tunnel",
+                    "operationId": "deletePeer",
+                    "parameters": [wait_param, idempotency_param, dry_run_param],
+                    "responses": responses(&[
+                        ("200", response("Plan of the deletion (dry_run=true)", &plan)),
+                        ("204", json!({ "description": "Peer deleted" })),
+                        ("202", job_queued),
+                        ("404", not_found),
+                    ], &error),
+                },
+            },
+            "/jobs/{id}": {
+                "get": {
+                    "summary": "Status and log of a queued job",
+                    "operationId": "getJob",
+                    "parameters": [{
+                        "name": "id",
+                        "in": "path",
+                        "required": true,
+                        "schema": job_id,
+                    }],
+                    "responses": responses(&[
+                        ("200", response("The job", &job)),
+                        ("404", json!({ "description": "Job not found" })),
+                    ], &error),
+                }
+            },
+            "/v1/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "operationId": "getOpenApi",
+                    "responses": responses(&[
+                        ("200", json!({
+                            "description": "OpenAPI document",
+                            "content": { "application/json": {} },
+                        })),
+                    ], &error),
+                }
+            },
+        },
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_document() {
+        let doc = openapi_document();
+        let schemas = &doc["components"]["schemas"];
+        for name in [
+            "Peer",
+            "PeerUpdate",
+            "PeerDbInfo",
+            "PeerState",
+            "ApiError",
+            "Job",
+            "JobAccepted",
+            "DryRunPlan",
+        ] {
+            assert!(schemas.get(name).is_some(), "missing schema {}", name);
+        }
+        assert!(
+            !schemas["Peer"]["required"]
+                .as_array()
+                .is_some_and(|required| required.iter().any(|field| field == "asn"))
+        );
+        // 所有引用都能在 components 中找到
+        let text = doc.to_string();
+        for reference in text.split("\"$ref\":\"#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap();
+            assert!(schemas.get(name).is_some(), "dangling reference {}", name);
+        }
+        assert_eq!(
+            doc["paths"]["/v1/peers/{asn}"]["put"]["requestBody"]["content"]["application/json"]["schema"]
+                ["$ref"],
+            "#/components/schemas/Peer"
+        );
+    }
+
+    #[test]
+    fn test_openapi_peer_operations() {
+        // 按客户端的方式解析序列化后的文档
+        let text = serde_json::to_string(&openapi_document()).unwrap();
+        let doc: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(doc["openapi"], "3.0.3");
+
+        let peer_path = &doc["paths"]["/v1/peers/{asn}"];
+        for (method, operation_id) in [
+            ("put", "putPeer"),
+            ("patch", "patchPeer"),
+            ("delete", "deletePeer"),
+        ] {
+            assert_eq!(
+                peer_path[method]["operationId"], operation_id,
+                "missing {} /v1/peers/{{asn}}",
+                method
+            );
+        }
+        assert_eq!(peer_path["parameters"][0]["name"], "asn");
+
+        let schema_ref = |operation: &Value| {
+            operation["content"]["application/json"]["schema"]["$ref"]
+                .as_str()
+                .map(str::to_string)
+        };
+        assert_eq!(
+            schema_ref(&peer_path["put"]["requestBody"]).as_deref(),
+            Some("#/components/schemas/Peer")
+        );
+        assert_eq!(
+            schema_ref(&peer_path["patch"]["requestBody"]).as_deref(),
+            Some("#/components/schemas/PeerUpdate")
+        );
+        assert_eq!(
+            schema_ref(&peer_path["get"]["responses"]["200"]).as_deref(),
+            Some("#/components/schemas/PeerDbInfo")
+        );
+        assert_eq!(
+            schema_ref(&peer_path["put"]["responses"]["201"]).as_deref(),
+            Some("#/components/schemas/PeerDbInfo")
+        );
+        assert!(
+            peer_path["delete"]["responses"]["204"].is_object(),
+            "{}",
+            peer_path["delete"]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::RateLimitConfig;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 记录的客户端数量超过该值时清理长时间不活跃的条目
+const MAX_TRACKED_KEYS: usize = 10_000;
+const IDLE_EXPIRY: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    pub static ref LIMITER: RateLimiter = RateLimiter::default();
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+#[derive(Debug)]
+struct FailedAuth {
+    count: u32,
+    window_start: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    failures: Mutex<HashMap<String, FailedAuth>>,
+}
+
+// 令牌桶：容量 burst，每秒补充 rate 个；rate 为 0 时不限速
+// 被拒绝时返回需要等待的时间
+fn take_token(
+    buckets: &mut HashMap<String, TokenBucket>,
+    key: &str,
+    rate: f64,
+    burst: u32,
+    now: Instant,
+) -> Result<(), Duration> {
+    if rate <= 0.0 {
+        return Ok(());
+    }
+    let burst = f64::from(burst.max(1));
+    let bucket = buckets.entry(key.to_string()).or_insert(TokenBucket {
+        tokens: burst,
+        last: now,
+    });
+    let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+    bucket.last = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else {
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+impl RateLimiter {
+    fn check(&self, key: &str, rate: f64, burst: u32, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() > MAX_TRACKED_KEYS {
+            buckets.retain(|_, bucket| now.saturating_duration_since(bucket.last) < IDLE_EXPIRY);
+        }
+        take_token(&mut buckets, key, rate, burst, now)
+    }
+
+    // 按来源（IP 或 Unix socket 的 uid）限速，在认证之前检查
+    pub fn check_client(
+        &self,
+        config: &RateLimitConfig,
+        client: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.check(
+            &format!("client:{}", client),
+            config.per_ip_rate,
+            config.per_ip_burst,
+            now,
+        )
+    }
+
+    // 按认证后的身份（token、mtls:CN、uid:N）限速
+    pub fn check_principal(
+        &self,
+        config: &RateLimitConfig,
+        principal: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.check(
+            &format!("principal:{}", principal),
+            config.per_token_rate,
+            config.per_token_burst,
+            now,
+        )
+    }
+
+    // 处于锁定期时返回剩余时间
+    pub fn check_lockout(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        match failures.get(client).and_then(|f| f.locked_until) {
+            Some(until) if until > now => Err(until - now),
+            _ => Ok(()),
+        }
+    }
+
+    // 记录一次认证失败，返回 true 表示该来源因此被锁定
+    pub fn record_auth_failure(
+        &self,
+        config: &RateLimitConfig,
+        client: &str,
+        now: Instant,
+    ) -> bool {
+        if config.max_failed_auth == 0 {
+            return false;
+        }
+        let window = Duration::from_secs(config.failed_auth_window);
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        if failures.len() > MAX_TRACKED_KEYS {
+            failures.retain(|_, f| {
+                f.locked_until.is_some_and(|until| until > now)
+                    || now.saturating_duration_since(f.window_start) < window
+            });
+        }
+        let entry = failures.entry(client.to_string()).or_insert(FailedAuth {
+            count: 0,
+            window_start: now,
+            locked_until: None,
+        });
+        if now.saturating_duration_since(entry.window_start) >= window {
+            entry.count = 0;
+            entry.window_start = now;
+        }
+        entry.count += 1;
+        if entry.count < config.max_failed_auth {
+            return false;
+        }
+        entry.count = 0;
+        entry.window_start = now;
+        entry.locked_until = Some(now + Duration::from_secs(config.lockout_seconds));
+        true
+    }
+
+    pub fn record_auth_success(&self, client: &str) {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.remove(client);
+    }
+}
+
+// Retry-After 以整秒表示，向上取整且至少为 1
+pub fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> RateLimitConfig {
+        RateLimitConfig {
+            per_ip_rate: 1.0,
+            per_ip_burst: 3,
+            per_token_rate: 0.0,
+            per_token_burst: 1,
+            max_failed_auth: 3,
+            failed_auth_window: 60,
+            lockout_seconds: 300,
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::default();
+        let config = test_config();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_client(&config, "192.0.2.1", now).is_ok());
+        }
+        let wait = limiter.check_client(&config, "192.0.2.1", now).unwrap_err();
+        assert_eq!(retry_after_secs(wait), 1);
+        // 其他来源不受影响
+        assert!(limiter.check_client(&config, "192.0.2.2", now).is_ok());
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check_client(&config, "192.0.2.1", later).is_ok());
+        assert!(limiter.check_client(&config, "192.0.2.1", later).is_err());
+        // rate 为 0 时不限速
+        for _ in 0..100 {
+            assert!(limiter.check_principal(&config, "token", now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_lockout() {
+        let limiter = RateLimiter::default();
+        let config = test_config();
+        let now = Instant::now();
+        assert!(!limiter.record_auth_failure(&config, "192.0.2.1", now));
+        assert!(!limiter.record_auth_failure(&config, "192.0.2.1", now));
+        assert!(limiter.check_lockout("192.0.2.1", now).is_ok());
+        assert!(limiter.record_auth_failure(&config, "192.0.2.1", now));
+        let wait = limiter.check_lockout("192.0.2.1", now).unwrap_err();
+        assert_eq!(retry_after_secs(wait), 300);
+        assert!(limiter.check_lockout("192.0.2.2", now).is_ok());
+        assert!(
+            limiter
+                .check_lockout("192.0.2.1", now + Duration::from_secs(300))
+                .is_ok()
+        );
+
+        // 窗口期外的失败重新计数，认证成功后清零
+        limiter.record_auth_failure(&config, "192.0.2.3", now);
+        limiter.record_auth_failure(&config, "192.0.2.3", now);
+        let later = now + Duration::from_secs(61);
+        assert!(!limiter.record_auth_failure(&config, "192.0.2.3", later));
+        limiter.record_auth_success("192.0.2.3");
+        assert!(!limiter.record_auth_failure(&config, "192.0.2.3", later));
+        assert!(!limiter.record_auth_failure(&config, "192.0.2.3", later));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::Db;
+use crate::audit::*;
+use crate::db::*;
+use crate::handler::*;
+use crate::jobs::{enqueue_response, should_queue};
+use crate::model::*;
+use crate::openapi::openapi_document;
+use crate::system::BirdReload;
+use http_types::{Method, Request, Response, StatusCode};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+const PEER_PATH_PREFIX: &str = "/v1/peers/";
+
+// /v1/peers/{asn} 中的 ASN，供审计日志在请求体缺少 asn 时使用
+pub fn path_asn(path: &str) -> Option<u64> {
+    path.strip_prefix(PEER_PATH_PREFIX)?.parse().ok()
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> http_types::Result<Response> {
+    let mut res = Response::new(status);
+    res.insert_header("Content-Type", "application/json; charset=utf-8");
+    res.set_body(serde_json::to_string(body)?);
+    Ok(res)
+}
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> http_types::Result<Response> {
+    json_response(
+        status,
+        &ApiError {
+            error: message.into(),
+        },
+    )
+}
+
+fn method_not_allowed(allow: &str) -> http_types::Result<Response> {
+    let mut res = error_response(StatusCode::MethodNotAllowed, "Method Not Allowed")?;
+    res.insert_header("Allow", allow);
+    Ok(res)
+}
+
+fn peer_not_found(asn: u64) -> http_types::Result<Response> {
+    error_response(StatusCode::NotFound, format!("Peer not found: {}", asn))
+}
+
+// 解析请求体，asn 取自路径；请求体中的 asn 可省略，但提供时必须与路径一致
+async fn parse_body<T: DeserializeOwned>(req: &mut Request, asn: u64) -> Result<T, String> {
+    let body = req.body_string().await.map_err(|e| e.to_string())?;
+    let mut value: Value = if body.trim().is_empty() {
+        Value::Object(Default::default())
+    } else {
+        serde_json::from_str(&body).map_err(|e| format!("Invalid JSON: {}", e))?
+    };
+    let Some(object) = value.as_object_mut() else {
+        return Err("Invalid JSON: expected an object".to_string());
+    };
+    match object.get("asn") {
+        None => {
+            object.insert("asn".to_string(), asn.into());
+        }
+        Some(body_asn) if body_asn.as_u64() == Some(asn) => {}
+        Some(_) => return Err(format!("asn in the body does not match the path: {}", asn)),
+    }
+    serde_json::from_value(value).map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+// 操作完成后返回 peer 的当前状态
+async fn peer_response(status: StatusCode, asn: u64, db: Db) -> http_types::Result<Response> {
+    match fetch_peer(asn, db).await {
+        Ok(Some(info)) => json_response(status, &info),
+        Ok(None) => peer_not_found(asn),
+        Err(e) => error_response(e.status, e.message),
+    }
+}
+
+async fn list_peers_v1(req: Request, db: Db) -> http_types::Result<Response> {
+    let state = match query_param(&req, "state") {
+        Some(value) => match PeerState::parse(&value) {
+            Some(state) => Some(state),
+            None => {
+                return error_response(StatusCode::BadRequest, format!("Invalid state: {}", value));
+            }
+        },
+        None => None,
+    };
+    let db_result: Result<Vec<PeerDbInfo>, PeerDbError> = smol::unblock(move || match db.lock() {
+        Ok(conn) => list_peers(&conn),
+        Err(e) => Err(PeerDbError::LockError(e.to_string())),
+    })
+    .await;
+    match db_result {
+        Ok(peers) => {
+            let peers: Vec<PeerDbInfo> = peers
+                .into_iter()
+                .filter(|p| state.is_none_or(|state| p.state == state))
+                .collect();
+            json_response(StatusCode::Ok, &peers)
+        }
+        Err(e) => error_response(
+            StatusCode::InternalServerError,
+            format!("Database error: {}", e),
+        ),
+    }
+}
+
+async fn get_peer_v1(asn: u64, db: Db) -> http_types::Result<Response> {
+    peer_response(StatusCode::Ok, asn, db).await
+}
+
+// 不存在（或已被拒绝）时按 /add 流程创建，否则替换全部字段
+// 返回 200 表示已替换，201 已创建，202 等待审批
+pub async fn put_peer_flow(peer: Peer, db: Db, reload: BirdReload) -> Result<StatusCode, OpError> {
+    match fetch_peer(peer.asn, db.clone()).await? {
+        Some(info) if info.state != PeerState::Rejected => {
+            update_peer_flow(PeerUpdate::from(peer), db, reload).await?;
+            Ok(StatusCode::Ok)
+        }
+        _ => match add_peer_flow(peer, db, false, reload).await? {
+            AddOutcome::Pending => Ok(StatusCode::Accepted),
+            _ => Ok(StatusCode::Created),
+        },
+    }
+}
+
+// PUT 的 dry run：按是否已存在分别给出 update 或 add 的计划
+async fn plan_put_flow(peer: Peer, db: Db) -> Result<DryRunPlan, OpError> {
+    match fetch_peer(peer.asn, db.clone()).await? {
+        Some(info) if info.state != PeerState::Rejected => {
+            plan_update_flow(PeerUpdate::from(peer), db).await
+        }
+        _ => match add_peer_flow(peer, db, true, BirdReload::Immediate).await? {
+            AddOutcome::WouldAdd(plan) => Ok(plan),
+            _ => Err(OpError::new(
+                StatusCode::Conflict,
+                "Peer was created concurrently",
+            )),
+        },
+    }
+}
+
+fn plan_response_v1(plan: Result<DryRunPlan, OpError>) -> http_types::Result<Response> {
+    match plan {
+        Ok(plan) => json_response(StatusCode::Ok, &plan),
+        Err(e) => error_response(e.status, e.message),
+    }
+}
+
+async fn put_peer_v1(mut req: Request, db: Db, asn: u64) -> http_types::Result<Response> {
+    let mut peer: Peer = match parse_body(&mut req, asn).await {
+        Ok(peer) => peer,
+        Err(msg) => return error_response(StatusCode::BadRequest, msg),
+    };
+    if is_dry_run(&req) {
+        return plan_response_v1(plan_put_flow(peer, db).await);
+    }
+    if should_queue(&req) {
+        if let Err(msg) = peer.validate() {
+            return error_response(StatusCode::BadRequest, msg);
+        }
+        return enqueue_response(&req, JobAction::Put, asn, &peer, db).await;
+    }
+    let status = match put_peer_flow(peer, db.clone(), BirdReload::Immediate).await {
+        Ok(status) => status,
+        Err(e) => return error_response(e.status, e.message),
+    };
+    let mut res = peer_response(status, asn, db).await?;
+    if status != StatusCode::Ok {
+        res.insert_header("Location", format!("{}{}", PEER_PATH_PREFIX, asn));
+    }
+    Ok(res)
+}
+
+async fn patch_peer_v1(mut req: Request, db: Db, asn: u64) -> http_types::Result<Response> {
+    let update: PeerUpdate = match parse_body(&mut req, asn).await {
+        Ok(update) => update,
+        Err(msg) => return error_response(StatusCode::BadRequest, msg),
+    };
+    match fetch_peer(asn, db.clone()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return peer_not_found(asn),
+        Err(e) => return error_response(e.status, e.message),
+    }
+    if is_dry_run(&req) {
+        return plan_response_v1(plan_update_flow(update, db).await);
+    }
+    if should_queue(&req) {
+        if let Err(e) = validate_update(&update, db.clone()).await {
+            return error_response(e.status, e.message);
+        }
+        return enqueue_response(&req, JobAction::Update, asn, &update, db).await;
+    }
+    if let Err(e) = update_peer_flow(update, db.clone(), BirdReload::Immediate).await {
+        return error_response(e.status, e.message);
+    }
+    peer_response(StatusCode::Ok, asn, db).await
+}
+
+async fn delete_peer_v1(req: Request, db: Db, asn: u64) -> http_types::Result<Response> {
+    match fetch_peer(asn, db.clone()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return peer_not_found(asn),
+        Err(e) => return error_response(e.status, e.message),
+    }
+    if is_dry_run(&req) {
+        return plan_response_v1(plan_del_flow(asn, db).await);
+    }
+    if should_queue(&req) {
+        return enqueue_response(
+            &req,
+            JobAction::Del,
+            asn,
+            &serde_json::json!({ "asn": asn }),
+            db,
+        )
+        .await;
+    }
+    match del_peer_flow(asn, db, BirdReload::Immediate).await {
+        Ok(()) => Ok(Response::new(StatusCode::NoContent)),
+        Err(e) => error_response(e.status, e.message),
+    }
+}
+
+// /v1 下的资源式接口，与旧的 POST 接口共用同一套流程
+pub async fn serve_v1(req: Request, db: Db) -> http_types::Result<Response> {
+    let path = req.url().path().to_string();
+    if path == "/v1/openapi.json" {
+        return match req.method() {
+            Method::Get => json_response(StatusCode::Ok, &openapi_document()),
+            _ => method_not_allowed("GET"),
+        };
+    }
+    if path == "/v1/peers" {
+        return match req.method() {
+            Method::Get => list_peers_v1(req, db).await,
+            _ => method_not_allowed("GET"),
+        };
+    }
+    let Some(asn) = path_asn(&path) else {
+        return error_response(StatusCode::NotFound, "Not Found");
+    };
+    match req.method() {
+        Method::Get => get_peer_v1(asn, db).await,
+        Method::Put => audited("put", req, db, |req, db| put_peer_v1(req, db, asn)).await,
+        Method::Patch => audited("update", req, db, |req, db| patch_peer_v1(req, db, asn)).await,
+        Method::Delete => audited("del", req, db, |req, db| delete_peer_v1(req, db, asn)).await,
+        _ => method_not_allowed("GET, PUT, PATCH, DELETE"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_asn() {
+        assert_eq!(path_asn("/v1/peers/4242420253"), Some(4242420253));
+        assert_eq!(path_asn("/v1/peers/"), None);
+        assert_eq!(path_asn("/v1/peers/abc"), None);
+        assert_eq!(path_asn("/v1/peers"), None);
+    }
+
+    #[test]
+    fn test_parse_body() {
+        smol::block_on(async {
+            let mut req = Request::new(Method::Patch, "http://localhost/v1/peers/4242420253");
+            req.set_body(r#"{"mtu": null}"#);
+            let update: PeerUpdate = parse_body(&mut req, 4242420253).await.unwrap();
+            assert_eq!(update.asn, 4242420253);
+            assert_eq!(update.mtu, Some(None));
+
+            let mut req = Request::new(Method::Put, "http://localhost/v1/peers/4242420253");
+            req.set_body(r#"{"asn": 4242420254}"#);
+            assert!(parse_body::<Peer>(&mut req, 4242420253).await.is_err());
+
+            let mut req = Request::new(Method::Put, "http://localhost/v1/peers/4242420253");
+            req.set_body("[]");
+            assert!(parse_body::<Peer>(&mut req, 4242420253).await.is_err());
+        });
+    }
+}
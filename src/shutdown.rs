@@ -0,0 +1,87 @@
+use crate::Db;
+use futures_lite::FutureExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// 请求处理期间持有，drop 时计数减一
+pub struct InFlightGuard(());
+
+impl InFlightGuard {
+    pub fn enter() -> Option<InFlightGuard> {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        // 先计数再检查，避免与 begin_shutdown 之间出现漏计
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(InFlightGuard(()))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn begin_shutdown() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+// 等待正在处理的请求结束，超时返回 false
+pub async fn drain(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while in_flight() > 0 {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        smol::Timer::after(DRAIN_POLL_INTERVAL).await;
+    }
+    true
+}
+
+// 等待数据库锁释放后将 SQLite 页缓存写回磁盘
+pub async fn flush_db(db: Db, timeout: Duration) -> Result<(), String> {
+    let flush = smol::unblock(move || {
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        conn.cache_flush().map_err(|e| e.to_string())
+    });
+    flush
+        .or(async {
+            smol::Timer::after(timeout).await;
+            Err("timed out waiting for the database lock".to_string())
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain() {
+        smol::block_on(async {
+            let guard = InFlightGuard::enter().unwrap();
+            assert!(!drain(Duration::from_millis(100)).await);
+            smol::spawn(async move {
+                smol::Timer::after(Duration::from_millis(50)).await;
+                drop(guard);
+            })
+            .detach();
+            assert!(drain(Duration::from_secs(5)).await);
+        });
+    }
+}
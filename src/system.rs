@@ -1,27 +1,323 @@
 use anyhow::{Ok, Result, anyhow};
 use lazy_static::lazy_static;
-use std::fs::write;
+use std::fmt;
 use std::process::Command;
-use std::sync::Mutex as StdMutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex as StdMutex, RwLock};
+use std::time::Duration;
 
-use crate::CONFIG;
+use crate::config;
+
+pub struct CommandOutput {
+    pub success: bool,
+    pub stderr: String,
+}
+
+// 对主机的全部修改（外部命令与配置文件）都经过该接口，测试中替换为记录调用的实现
+pub trait SystemBackend: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput>;
+    fn read_file(&self, path: &str) -> std::io::Result<String>;
+    fn write_file(&self, path: &str, content: &str) -> std::io::Result<()>;
+    fn remove_file(&self, path: &str) -> std::io::Result<()>;
+    // 返回目录下的文件名（不含路径）
+    fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>>;
+}
+
+pub struct ProcessBackend;
+
+impl SystemBackend for ProcessBackend {
+    fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+        let output = Command::new(program).args(args).output()?;
+        std::io::Result::Ok(CommandOutput {
+            success: output.status.success(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn read_file(&self, path: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn write_file(&self, path: &str, content: &str) -> std::io::Result<()> {
+        std::fs::write(path, content)
+    }
+
+    fn remove_file(&self, path: &str) -> std::io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>> {
+        list_file_names(std::path::Path::new(path))
+    }
+}
+
+pub(crate) fn list_file_names(dir: &std::path::Path) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    std::io::Result::Ok(names)
+}
+
+static BACKEND: RwLock<Option<Arc<dyn SystemBackend>>> = RwLock::new(None);
+
+fn backend() -> Arc<dyn SystemBackend> {
+    match BACKEND.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(backend) => backend.clone(),
+        None => Arc::new(ProcessBackend),
+    }
+}
+
+// 替换系统后端并返回之前的后端，None 表示使用 ProcessBackend
+#[cfg(test)]
+pub fn replace_backend(backend: Option<Arc<dyn SystemBackend>>) -> Option<Arc<dyn SystemBackend>> {
+    std::mem::replace(
+        &mut *BACKEND.write().unwrap_or_else(|e| e.into_inner()),
+        backend,
+    )
+}
 
 fn save(path: &str, content: &str) -> Result<()> {
-    write(path, content)?;
+    backend().write_file(path, content)?;
     Ok(())
 }
 
 fn delete(path: &str) -> Result<()> {
-    std::fs::remove_file(path)?;
+    backend().remove_file(path)?;
     Ok(())
 }
 
+// 文件已不存在时视为成功：/del 在删除文件之后的步骤（如 birdc configure）失败时，
+// peer 仍保留在数据库中，重试时需要跳过已经删除的文件
+fn delete_if_exists(path: &str) -> Result<()> {
+    match delete(path) {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
 fn delete_config(wg_config_path: &str, bird_config_path: &str) -> Result<()> {
-    delete(wg_config_path)?;
-    delete(bird_config_path)?;
+    delete_if_exists(wg_config_path)?;
+    delete_if_exists(bird_config_path)?;
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str], failure: &str) -> Result<()> {
+    let output = backend().run(program, args)?;
+    if !output.success {
+        return Err(anyhow!("{}: {}", failure, output.stderr));
+    }
     Ok(())
 }
 
+// 执行不修改主机的检查命令（如 ping），返回命令是否成功
+pub fn run_check(program: &str, args: &[&str]) -> std::io::Result<bool> {
+    backend().run(program, args).map(|output| output.success)
+}
+
+// 读取已部署的配置文件，不存在或无法读取时返回 None
+pub fn read_config_file(path: &str) -> Option<String> {
+    backend().read_file(path).ok()
+}
+
+pub fn read_file(path: &str) -> std::io::Result<String> {
+    backend().read_file(path)
+}
+
+pub fn list_dir(path: &str) -> std::io::Result<Vec<String>> {
+    backend().list_dir(path)
+}
+
+// 一条系统命令及其失败时的错误信息，dry run 时只列出而不执行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemCommand {
+    program: String,
+    args: Vec<String>,
+    failure: &'static str,
+}
+
+impl SystemCommand {
+    fn new(program: &str, args: &[&str], failure: &'static str) -> Self {
+        SystemCommand {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            failure,
+        }
+    }
+
+    fn run(&self) -> Result<()> {
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        run(&self.program, &args, self.failure)
+    }
+}
+
+impl fmt::Display for SystemCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        std::result::Result::Ok(())
+    }
+}
+
+fn unsupported_init_system() -> anyhow::Error {
+    anyhow!("Unsupported init system: {}", config().env.init_system)
+}
+
+// OpenRC 下每个隧道对应 /etc/init.d 中的一个符号链接
+pub fn openrc_link_path(interface_name: &str) -> Option<String> {
+    (config().env.init_system == "openrc")
+        .then(|| format!("/etc/init.d/wg-quick.{}", interface_name))
+}
+
+pub fn reconfigure_command() -> SystemCommand {
+    SystemCommand::new(
+        &config().env.birdc_path,
+        &["configure"],
+        "Failed to reconfigure bird daemon",
+    )
+}
+
+// 隧道服务的基本操作，systemd 与 OpenRC 下的命令与错误信息只在这里定义
+#[derive(Debug, Clone, Copy)]
+enum TunnelAction {
+    Start,
+    Stop,
+    Restart,
+    // 设置开机启动
+    Enable,
+    // 取消开机启动
+    Disable,
+}
+
+fn tunnel_command(interface_name: &str, action: TunnelAction) -> Result<SystemCommand> {
+    let config = config();
+    match config.env.init_system.as_str() {
+        "systemd" => {
+            let item = format!("wg-quick@{}", interface_name);
+            let (verb, failure) = match action {
+                TunnelAction::Start => ("start", "Failed to start wireguard tunnel"),
+                TunnelAction::Stop => ("stop", "Failed to stop wireguard tunnel"),
+                TunnelAction::Restart => ("restart", "Failed to restart wireguard tunnel"),
+                TunnelAction::Enable => (
+                    "enable",
+                    "Failed to set starting wireguard tunnel at startup",
+                ),
+                TunnelAction::Disable => ("disable", "Failed to disable wireguard tunnel"),
+            };
+            Ok(SystemCommand::new(
+                &config.env.systemctl_path,
+                &[verb, &item],
+                failure,
+            ))
+        }
+        "openrc" => {
+            let item = format!("wg-quick.{}", interface_name);
+            let rc_service = |verb: &str, failure| {
+                SystemCommand::new(&config.env.rc_service_path, &[&item, verb], failure)
+            };
+            let rc_update = |verb: &str, failure| {
+                SystemCommand::new(
+                    &config.env.rc_update_path,
+                    &[verb, &item, "default"],
+                    failure,
+                )
+            };
+            Ok(match action {
+                TunnelAction::Start => rc_service("start", "Failed to start service"),
+                TunnelAction::Stop => rc_service("stop", "Failed to stop service"),
+                TunnelAction::Restart => rc_service("restart", "Failed to restart service"),
+                TunnelAction::Enable => {
+                    rc_update("add", "Failed to add service to default runlevel")
+                }
+                TunnelAction::Disable => {
+                    rc_update("del", "Failed to remove service from default runlevel")
+                }
+            })
+        }
+        _ => Err(unsupported_init_system()),
+    }
+}
+
+// BGP 会话名与接口名相同
+fn protocol_command(interface_name: &str, enable: bool) -> SystemCommand {
+    let (verb, failure) = if enable {
+        ("enable", "Failed to enable BGP protocol")
+    } else {
+        ("disable", "Failed to disable BGP protocol")
+    };
+    SystemCommand::new(&config().env.birdc_path, &[verb, interface_name], failure)
+}
+
+pub fn restart_commands(interface_name: &str) -> Result<Vec<SystemCommand>> {
+    Ok(vec![tunnel_command(interface_name, TunnelAction::Restart)?])
+}
+
+// 启动隧道并设置开机启动，之后还需要 birdc configure
+pub fn apply_commands(interface_name: &str) -> Result<Vec<SystemCommand>> {
+    let mut commands = Vec::new();
+    if let Some(link_file_path) = openrc_link_path(interface_name) {
+        commands.push(SystemCommand::new(
+            "ln",
+            &["-sf", "/etc/init.d/wg-quick", &link_file_path],
+            "Failed to create symbolic link",
+        ));
+    }
+    commands.push(tunnel_command(interface_name, TunnelAction::Start)?);
+    commands.push(tunnel_command(interface_name, TunnelAction::Enable)?);
+    Ok(commands)
+}
+
+// 停止隧道并取消开机启动；之后删除配置文件（OpenRC 还有符号链接）并执行 birdc configure
+pub fn remove_commands(interface_name: &str, tunnel_active: bool) -> Result<Vec<SystemCommand>> {
+    if !tunnel_active {
+        return Ok(Vec::new());
+    }
+    // systemd 先取消开机启动再停止，OpenRC 先停止服务再移出运行级别
+    let order = if config().env.init_system == "openrc" {
+        [TunnelAction::Stop, TunnelAction::Disable]
+    } else {
+        [TunnelAction::Disable, TunnelAction::Stop]
+    };
+    order
+        .into_iter()
+        .map(|action| tunnel_command(interface_name, action))
+        .collect()
+}
+
+// 停止隧道、取消开机启动，并在 BIRD 中禁用对应的 BGP 会话
+fn disable_commands(interface_name: &str) -> Result<Vec<SystemCommand>> {
+    Ok(vec![
+        tunnel_command(interface_name, TunnelAction::Stop)?,
+        tunnel_command(interface_name, TunnelAction::Disable)?,
+        protocol_command(interface_name, false),
+    ])
+}
+
+// 以 disabled 状态导入的 peer 从未启动过，OpenRC 下还没有符号链接，因此与部署时相同
+fn enable_commands(interface_name: &str) -> Result<Vec<SystemCommand>> {
+    let mut commands = apply_commands(interface_name)?;
+    commands.push(protocol_command(interface_name, true));
+    Ok(commands)
+}
+
+// 启动时确保已禁用的 peer 保持关闭
+fn enforce_disabled_commands(interface_name: &str) -> Result<Vec<SystemCommand>> {
+    Ok(vec![
+        tunnel_command(interface_name, TunnelAction::Stop)?,
+        protocol_command(interface_name, false),
+    ])
+}
+
 lazy_static! {
     static ref SYSTEM_OP_LOCK: StdMutex<()> = StdMutex::new(());
 }
@@ -40,180 +336,259 @@ pub fn save_config(
     Ok(())
 }
 
-pub fn apply_config(interface_name: &str) -> Result<()> {
+// 仅在内容变化时写入，返回是否发生了变化
+pub fn update_config_file(path: &str, content: &str) -> Result<bool> {
     let _guard = SYSTEM_OP_LOCK
         .lock()
         .map_err(|e| anyhow!("Mutex lock error: {}", e))?;
-    if CONFIG.env.init_system == "systemd" {
-        let item = format!("wg-quick@{}", interface_name);
-
-        // systemctl start wg-quick@interface_name
-        let args = vec!["start", item.as_str()];
-        let output = Command::new(&CONFIG.env.systemctl_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to start wireguard tunnel: {}", stderr));
+    match read_config_file(path) {
+        Some(current) if current == content => Ok(false),
+        _ => {
+            save(path, content)?;
+            Ok(true)
         }
+    }
+}
 
-        // systemctl enable wg-quick@interface_name
-        let args = vec!["enable", item.as_str()];
-        let output = Command::new(&CONFIG.env.systemctl_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!(
-                "Failed to set starting wireguard tunnel at startup: {}",
-                stderr
-            ));
-        }
+// 合并短时间内的多个 birdc configure 请求
+// 第一个请求等待 birdc_configure_delay_ms 后执行一次，期间加入的请求共享同一结果
+#[derive(Default)]
+struct Batch {
+    result: StdMutex<Option<std::result::Result<(), String>>>,
+    done: Condvar,
+}
 
-        // birdc configure
-        let args = vec!["configure"];
-        let output = Command::new(&CONFIG.env.birdc_path).args(&args).output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to reconfigure bird daemon: {}", stderr));
-        }
+#[derive(Default)]
+struct Coalescer {
+    pending: StdMutex<Option<Arc<Batch>>>,
+}
 
-        Ok(())
-    } else if CONFIG.env.init_system == "openrc" {
-        // ln -s /etc/init.d/wg-quick /etc/init.d/wg-quick.interface_name
-        let item = format!("wg-quick.{}", interface_name);
-        let link_file_path = format!("/etc/init.d/{}", item.as_str());
-        let args = vec!["-s", "/etc/init.d/wg-quick", &link_file_path];
-        let output = Command::new("ln").args(&args).output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to create symbolic link: {}", stderr));
+impl Coalescer {
+    fn run<F>(&self, delay: Duration, f: F) -> std::result::Result<(), String>
+    where
+        F: FnOnce() -> std::result::Result<(), String>,
+    {
+        let (batch, leader) = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            match pending.as_ref() {
+                Some(batch) => (batch.clone(), false),
+                None => {
+                    let batch = Arc::new(Batch::default());
+                    *pending = Some(batch.clone());
+                    (batch, true)
+                }
+            }
+        };
+        if leader {
+            std::thread::sleep(delay);
+            // 关闭当前批次，之后的请求进入下一批
+            self.pending
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take();
+            let result = f();
+            *batch.result.lock().unwrap_or_else(|e| e.into_inner()) = Some(result.clone());
+            batch.done.notify_all();
+            return result;
         }
-
-        // rc-service wg-quick.interface_name start
-        let args = vec![&item, "start"];
-        let output = Command::new(&CONFIG.env.rc_service_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to start service: {}", stderr));
+        let mut result = batch.result.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(result) = result.as_ref() {
+                return result.clone();
+            }
+            result = batch.done.wait(result).unwrap_or_else(|e| e.into_inner());
         }
+    }
+}
 
-        // rc-update add wg-quick.interface_name default
-        let args = vec!["add", &item, "default"];
-        let output = Command::new(&CONFIG.env.rc_update_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!(
-                "Failed to add service to default runlevel: {}",
-                stderr
-            ));
-        }
+lazy_static! {
+    static ref BIRD_RECONFIGURE: Coalescer = Coalescer::default();
+}
+
+// 调用方需已释放 SYSTEM_OP_LOCK，否则等待期间其他请求无法写入配置并加入批次
+pub fn reconfigure_bird() -> Result<()> {
+    let delay = Duration::from_millis(config().env.birdc_configure_delay_ms);
+    let command = reconfigure_command();
+    BIRD_RECONFIGURE
+        .run(delay, || {
+            let _guard = SYSTEM_OP_LOCK
+                .lock()
+                .map_err(|e| format!("Mutex lock error: {}", e))?;
+            command.run().map_err(|e| e.to_string())
+        })
+        .map_err(|e| anyhow!(e))
+}
+
+// 写入 BIRD 配置后何时执行 birdc configure：单个请求立即执行（并发的请求由 reconfigure_bird 合并），
+// 批量操作（导入、任务队列）只记录需要执行，全部条目写入后由调用方统一执行一次
+#[derive(Debug, Clone)]
+pub enum BirdReload {
+    Immediate,
+    Deferred(Arc<AtomicBool>),
+}
+
+impl BirdReload {
+    pub fn deferred() -> Self {
+        BirdReload::Deferred(Arc::new(AtomicBool::new(false)))
+    }
 
-        // birdc configure
-        let args = vec!["configure"];
-        let output = Command::new(&CONFIG.env.birdc_path).args(&args).output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to reconfigure bird daemon: {}", stderr));
+    pub fn request(&self) -> Result<()> {
+        match self {
+            BirdReload::Immediate => reconfigure_bird(),
+            BirdReload::Deferred(pending) => {
+                pending.store(true, Ordering::SeqCst);
+                Ok(())
+            }
         }
+    }
 
-        Ok(())
-    } else {
-        Err(anyhow!(
-            "Unsupported init system: {}",
-            CONFIG.env.init_system
-        ))
+    // 是否有延后执行的 birdc configure 尚未执行
+    pub fn is_pending(&self) -> bool {
+        match self {
+            BirdReload::Immediate => false,
+            BirdReload::Deferred(pending) => pending.load(Ordering::SeqCst),
+        }
     }
 }
 
+// 持有 SYSTEM_OP_LOCK 依次执行，遇到失败的命令立即返回
+fn run_commands(commands: &[SystemCommand]) -> Result<()> {
+    let _guard = SYSTEM_OP_LOCK
+        .lock()
+        .map_err(|e| anyhow!("Mutex lock error: {}", e))?;
+    commands.iter().try_for_each(SystemCommand::run)
+}
+
+pub fn restart_tunnel(interface_name: &str) -> Result<()> {
+    run_commands(&restart_commands(interface_name)?)
+}
+
+// 之后还需要重新加载 BIRD，见 BirdReload
+pub fn apply_config(interface_name: &str) -> Result<()> {
+    run_commands(&apply_commands(interface_name)?)
+}
+
+pub fn disable_peer(interface_name: &str) -> Result<()> {
+    run_commands(&disable_commands(interface_name)?)
+}
+
+pub fn enable_peer(interface_name: &str) -> Result<()> {
+    run_commands(&enable_commands(interface_name)?)
+}
+
+// 启动隧道，已在运行时无副作用
+pub fn start_tunnel(interface_name: &str) -> Result<()> {
+    run_commands(&[tunnel_command(interface_name, TunnelAction::Start)?])
+}
+
+// 启动时确保已禁用的 peer 保持关闭，重复执行无副作用
+pub fn enforce_disabled(interface_name: &str) -> Result<()> {
+    run_commands(&enforce_disabled_commands(interface_name)?)
+}
+
+// tunnel_active 为 false 时隧道已被禁用，跳过停止隧道与取消开机启动；之后还需要重新加载 BIRD
 pub fn remove_config(
     interface_name: &str,
     wg_config_path: &str,
     bird_config_path: &str,
+    tunnel_active: bool,
 ) -> Result<()> {
-    let _guard = SYSTEM_OP_LOCK
+    let commands = remove_commands(interface_name, tunnel_active)?;
+    let guard = SYSTEM_OP_LOCK
         .lock()
         .map_err(|e| anyhow!("Mutex lock error: {}", e))?;
-    if CONFIG.env.init_system == "systemd" {
-        let item = format!("wg-quick@{}", interface_name);
-
-        let args = vec!["disable", item.as_str()];
-        let output = Command::new(&CONFIG.env.systemctl_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to disable wireguard tunnel: {}", stderr));
-        }
-
-        let args = vec!["stop", item.as_str()];
-        let output = Command::new(&CONFIG.env.systemctl_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to stop wireguard tunnel: {}", stderr));
-        }
-
-        delete_config(wg_config_path, bird_config_path)?;
+    commands.iter().try_for_each(SystemCommand::run)?;
+    if let Some(link_file_path) = openrc_link_path(interface_name) {
+        delete_if_exists(&link_file_path)?;
+    }
+    delete_config(wg_config_path, bird_config_path)?;
+    drop(guard);
+    Ok(())
+}
 
-        let args = vec!["configure"];
-        let output = Command::new(&CONFIG.env.birdc_path).args(&args).output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to reconfigure bird daemon: {}", stderr));
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-        Ok(())
-    } else if CONFIG.env.init_system == "openrc" {
-        let item = format!("wg-quick.{}", interface_name);
-        // rc-service wg-quick.interface_name stop
-        let args = vec![&item, "stop"];
-        let output = Command::new(&CONFIG.env.rc_service_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to stop service: {}", stderr));
-        }
+    #[test]
+    fn test_system_command_display() {
+        let command = SystemCommand::new(
+            "/usr/bin/systemctl",
+            &["start", "wg-quick@dn42_0253"],
+            "Failed to start wireguard tunnel",
+        );
+        assert_eq!(
+            command.to_string(),
+            "/usr/bin/systemctl start wg-quick@dn42_0253"
+        );
+    }
 
-        // rc-update del wg-quick.interface_name default
-        let args = vec!["del", &item, "default"];
-        let output = Command::new(&CONFIG.env.rc_update_path)
-            .args(&args)
-            .output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!(
-                "Failed to remove service from default runlevel: {}",
-                stderr
-            ));
-        }
+    #[test]
+    fn test_peer_state_commands() {
+        let h = Harness::setup();
+        disable_peer("dn42_0253").unwrap();
+        enable_peer("dn42_0253").unwrap();
+        start_tunnel("dn42_0253").unwrap();
+        enforce_disabled("dn42_0253").unwrap();
+        assert_eq!(
+            h.backend.commands(),
+            [
+                "/usr/bin/systemctl stop wg-quick@dn42_0253",
+                "/usr/bin/systemctl disable wg-quick@dn42_0253",
+                "/usr/sbin/birdc disable dn42_0253",
+                "/usr/bin/systemctl start wg-quick@dn42_0253",
+                "/usr/bin/systemctl enable wg-quick@dn42_0253",
+                "/usr/sbin/birdc enable dn42_0253",
+                "/usr/bin/systemctl start wg-quick@dn42_0253",
+                "/usr/bin/systemctl stop wg-quick@dn42_0253",
+                "/usr/sbin/birdc disable dn42_0253",
+            ]
+        );
 
-        // delete /etc/init.d/wg-quick.interface_name
-        let link_file_path = format!("/etc/init.d/{}", item.as_str());
-        delete(&link_file_path)?;
+        // 失败时返回该命令的错误信息，后续命令不再执行
+        h.backend.clear_commands();
+        h.backend.fail_on("disable wg-quick", "unit not found");
+        let err = disable_peer("dn42_0253").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to disable wireguard tunnel: unit not found"
+        );
+        assert_eq!(h.backend.commands().len(), 2);
+    }
 
-        // delete config files
-        delete_config(wg_config_path, bird_config_path)?;
+    #[test]
+    fn test_coalesce_reconfigure() {
+        let coalescer = Arc::new(Coalescer::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let run = |fail: bool| {
+            let coalescer = coalescer.clone();
+            let calls = calls.clone();
+            std::thread::spawn(move || {
+                coalescer.run(Duration::from_millis(200), || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    if fail {
+                        Err("Failed to reconfigure bird daemon".to_string())
+                    } else {
+                        std::result::Result::Ok(())
+                    }
+                })
+            })
+        };
 
-        // birdc configure
-        let args = vec!["configure"];
-        let output = Command::new(&CONFIG.env.birdc_path).args(&args).output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to reconfigure bird daemon: {}", stderr));
+        // 窗口期内的请求只执行一次，且都收到同一个结果
+        let first = run(true);
+        std::thread::sleep(Duration::from_millis(50));
+        let rest: Vec<_> = (0..4).map(|_| run(false)).collect();
+        assert!(first.join().unwrap().is_err());
+        for handle in rest {
+            assert!(handle.join().unwrap().is_err());
         }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
 
-        Ok(())
-    } else {
-        Err(anyhow!(
-            "Unsupported init system: {}",
-            CONFIG.env.init_system
-        ))
+        // 上一批完成后的请求进入新的批次
+        assert!(run(false).join().unwrap().is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
     }
 }
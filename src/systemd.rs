@@ -0,0 +1,167 @@
+use std::ffi::OsStr;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+// sd_listen_fds(3) 约定：传入的文件描述符从 3 开始
+const LISTEN_FDS_START: RawFd = 3;
+
+// 向 $NOTIFY_SOCKET 发送状态，未由 systemd 以 Type=notify 启动时什么也不做
+pub fn notify(state: &str) -> io::Result<bool> {
+    notify_socket(std::env::var_os("NOTIFY_SOCKET").as_deref(), state)
+}
+
+fn notify_socket(path: Option<&OsStr>, state: &str) -> io::Result<bool> {
+    let Some(path) = path else {
+        return Ok(false);
+    };
+    let path = path.to_string_lossy();
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+// 通知失败不影响服务本身，只记录日志
+pub fn notify_or_log(state: &str) {
+    if let Err(e) = notify(state) {
+        eprintln!("Failed to notify systemd: {}", e);
+    }
+}
+
+// 未设置 PID 时视为发给当前进程
+fn targets_pid(pid: Option<&str>, own_pid: u32) -> bool {
+    match pid {
+        Some(pid) => pid.parse::<u32>().ok() == Some(own_pid),
+        None => true,
+    }
+}
+
+// WatchdogSec= 启用时返回发送 WATCHDOG=1 的间隔（超时时间的一半）
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    let usec = usec?.parse::<u64>().ok()?;
+    if usec == 0 || !targets_pid(pid, own_pid) {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+// 返回传给当前进程的 socket 数量，LISTEN_PID 缺失或不是当前进程时为 0
+fn parse_listen_fds(count: Option<&str>, pid: Option<&str>, own_pid: u32) -> io::Result<RawFd> {
+    let (Some(count), Some(_)) = (count, pid) else {
+        return Ok(0);
+    };
+    if !targets_pid(pid, own_pid) {
+        return Ok(0);
+    }
+    match count.parse::<RawFd>() {
+        Ok(count) if count >= 0 => Ok(count),
+        _ => Err(io::Error::other(format!("Invalid LISTEN_FDS: {}", count))),
+    }
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fcntl on a descriptor handed to us by systemd, no memory is involved
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// 取得 systemd socket activation 传入的 TCP 监听 socket，未启用时返回空列表
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    let count = parse_listen_fds(
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::process::id(),
+    )?;
+
+    let mut listeners = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // 避免 systemctl、birdc 等子进程继承监听 socket
+        set_cloexec(fd)?;
+        // SAFETY: systemd passes ownership of LISTEN_FDS descriptors starting at 3 to this process
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        listener.local_addr().map_err(|e| {
+            io::Error::other(format!(
+                "Socket activation fd {} is not a TCP socket: {}",
+                fd, e
+            ))
+        })?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        assert!(!notify_socket(None, "READY=1").unwrap());
+
+        let path = std::env::temp_dir().join(format!("dn42-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        assert!(notify_socket(Some(path.as_os_str()), "READY=1").unwrap());
+        let mut buf = [0u8; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+
+        // 以 @ 开头的是 abstract socket
+        let name = format!("dn42-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let socket = UnixDatagram::bind_addr(&addr).unwrap();
+        let path = format!("@{}", name);
+        assert!(notify_socket(Some(OsStr::new(&path)), "STOPPING=1").unwrap());
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+    }
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(
+            parse_watchdog(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(parse_watchdog(Some("30000000"), Some("43"), 42), None);
+        assert_eq!(parse_watchdog(Some("30000000"), Some("abc"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(Some("abc"), None, 42), None);
+        assert_eq!(parse_watchdog(None, Some("42"), 42), None);
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(parse_listen_fds(Some("2"), Some("42"), 42).unwrap(), 2);
+        assert_eq!(parse_listen_fds(Some("0"), Some("42"), 42).unwrap(), 0);
+        // 发给其他进程（例如父进程）的 socket 不属于当前进程
+        assert_eq!(parse_listen_fds(Some("2"), Some("43"), 42).unwrap(), 0);
+        assert_eq!(parse_listen_fds(Some("2"), Some("abc"), 42).unwrap(), 0);
+        assert_eq!(parse_listen_fds(Some("2"), None, 42).unwrap(), 0);
+        assert_eq!(parse_listen_fds(None, Some("42"), 42).unwrap(), 0);
+        assert!(parse_listen_fds(Some("abc"), Some("42"), 42).is_err());
+        assert!(parse_listen_fds(Some("-1"), Some("42"), 42).is_err());
+    }
+}
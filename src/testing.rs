@@ -0,0 +1,204 @@
+// 集成测试使用的假系统后端与测试环境，仅在测试中编译
+use crate::db::init_db;
+use crate::system::{CommandOutput, SystemBackend, list_file_names, replace_backend};
+use crate::{AppConfig, Db, replace_config};
+use lazy_static::lazy_static;
+use rusqlite::Connection;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub const TEST_SECRET: &str = "test-secret";
+pub const TEST_PRIVATE_KEY: &str = "gI6EdUSYvn8ugXOt8QQD6Yc+JyiZxIhp3GInSWRfWGE=";
+
+// 配置中的路径保持默认值，由 FakeBackend 映射到临时目录下
+const TEST_CONFIG: &str = r#"
+[API]
+api_port = 4242
+listen_address_v4 = "127.0.0.1"
+listen_address_v6 = ""
+secret = "test-secret"
+
+[Peer]
+link_local = "fe80::253"
+wireguard_private_key = "gI6EdUSYvn8ugXOt8QQD6Yc+JyiZxIhp3GInSWRfWGE="
+port_prefix_number = 2
+
+[Environment]
+init_system = "systemd"
+systemctl_path = "/usr/bin/systemctl"
+rc_service_path = "/sbin/rc-service"
+rc_update_path = "/sbin/rc-update"
+birdc_path = "/usr/sbin/birdc"
+birdc_configure_delay_ms = 0
+
+[Paths]
+database = ":memory:"
+wireguard_dir = "/etc/wireguard"
+bird_peers_dir = "/etc/bird/peers"
+
+[RateLimit]
+per_ip_rate = 0.0
+per_token_rate = 0.0
+max_failed_auth = 0
+"#;
+
+lazy_static! {
+    // 配置与系统后端是全局的，使用它们的测试需要依次执行
+    static ref HARNESS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+static NEXT_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+// 记录所有命令而不执行，文件读写限制在临时目录中
+pub struct FakeBackend {
+    root: PathBuf,
+    commands: Mutex<Vec<String>>,
+    // 命令行包含该片段时返回失败，stderr 为对应的内容
+    failures: Mutex<Vec<(String, String)>>,
+}
+
+impl FakeBackend {
+    fn new(root: PathBuf) -> Self {
+        FakeBackend {
+            root,
+            commands: Mutex::new(Vec::new()),
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn host_path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    pub fn clear_commands(&self) {
+        self.commands.lock().unwrap().clear();
+    }
+
+    pub fn fail_on(&self, pattern: &str, stderr: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .push((pattern.to_string(), stderr.to_string()));
+    }
+
+    pub fn clear_failures(&self) {
+        self.failures.lock().unwrap().clear();
+    }
+
+    pub fn file(&self, path: &str) -> Option<String> {
+        std::fs::read_to_string(self.host_path(path)).ok()
+    }
+}
+
+impl SystemBackend for FakeBackend {
+    fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+        let line = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.commands.lock().unwrap().push(line.clone());
+        let failure = self
+            .failures
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(pattern, _)| line.contains(pattern.as_str()))
+            .map(|(_, stderr)| stderr.clone());
+        Ok(CommandOutput {
+            success: failure.is_none(),
+            stderr: failure.unwrap_or_default(),
+        })
+    }
+
+    fn read_file(&self, path: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(self.host_path(path))
+    }
+
+    fn write_file(&self, path: &str, content: &str) -> std::io::Result<()> {
+        std::fs::write(self.host_path(path), content)
+    }
+
+    fn remove_file(&self, path: &str) -> std::io::Result<()> {
+        std::fs::remove_file(self.host_path(path))
+    }
+
+    fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>> {
+        list_file_names(&self.host_path(path))
+    }
+}
+
+// 一次集成测试的环境：测试配置、内存数据库、假系统后端与临时根目录
+pub struct Harness {
+    pub db: Db,
+    pub backend: Arc<FakeBackend>,
+    root: PathBuf,
+    extra_config: String,
+    // 测试前的配置与系统后端，结束时恢复
+    previous_config: Option<Arc<AppConfig>>,
+    previous_backend: Option<Arc<dyn SystemBackend>>,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    pub fn setup() -> Self {
+        Harness::with_config("")
+    }
+
+    // extra 中的配置段追加在测试配置之后，不能与已有的段重复
+    pub fn with_config(extra: &str) -> Self {
+        let guard = HARNESS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config = test_config(extra);
+        let root = std::env::temp_dir().join(format!(
+            "dn42-autopeer-test-{}-{}",
+            std::process::id(),
+            NEXT_ROOT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let backend = Arc::new(FakeBackend::new(root.clone()));
+        create_config_dirs(&backend, &config);
+        let previous_config = replace_config(Some(Arc::new(config)));
+        let previous_backend = replace_backend(Some(backend.clone()));
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        Harness {
+            db: Arc::new(Mutex::new(conn)),
+            backend,
+            root,
+            extra_config: extra.to_string(),
+            previous_config,
+            previous_backend,
+            _guard: guard,
+        }
+    }
+
+    // 在测试配置的基础上修改后替换当前配置，相当于 SIGHUP 重新加载
+    pub fn reload_config(&self, change: impl FnOnce(&mut AppConfig)) {
+        let mut config = test_config(&self.extra_config);
+        change(&mut config);
+        create_config_dirs(&self.backend, &config);
+        replace_config(Some(Arc::new(config)));
+    }
+}
+
+fn test_config(extra: &str) -> AppConfig {
+    toml::from_str(&format!("{}\n{}", TEST_CONFIG, extra)).unwrap()
+}
+
+fn create_config_dirs(backend: &FakeBackend, config: &AppConfig) {
+    for dir in [&config.paths.wireguard_dir, &config.paths.bird_peers_dir] {
+        std::fs::create_dir_all(backend.host_path(dir)).unwrap();
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        replace_config(self.previous_config.take());
+        replace_backend(self.previous_backend.take());
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::UnixSocketConfig;
+use async_io::Async;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+// getpwnam_r/getgrnam_r 使用的缓冲区大小，足以容纳成员较多的组
+const NSS_BUFFER_SIZE: usize = 64 * 1024;
+
+// 通过 SO_PEERCRED 取得的对端进程身份，附加到该连接的每个请求上
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+pub fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len point to valid, correctly sized memory for SO_PEERCRED
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+// 解析八进制权限字符串，例如 `0660` 或 `660`
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+// 用户名或数字 uid
+pub fn lookup_user(name: &str) -> Result<u32, String> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let cname = CString::new(name).map_err(|_| format!("Invalid user name: {}", name))?;
+    // SAFETY: passwd is plain data, zero is a valid bit pattern for it
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; NSS_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers reference live buffers of the given sizes
+    let rc = unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(format!(
+            "Failed to look up user {}: {}",
+            name,
+            io::Error::from_raw_os_error(rc)
+        ));
+    }
+    if result.is_null() {
+        return Err(format!("Unknown user: {}", name));
+    }
+    Ok(pwd.pw_uid)
+}
+
+// 组名或数字 gid
+pub fn lookup_group(name: &str) -> Result<u32, String> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let cname = CString::new(name).map_err(|_| format!("Invalid group name: {}", name))?;
+    // SAFETY: group is plain data, zero is a valid bit pattern for it
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; NSS_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers reference live buffers of the given sizes
+    let rc = unsafe {
+        libc::getgrnam_r(
+            cname.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(format!(
+            "Failed to look up group {}: {}",
+            name,
+            io::Error::from_raw_os_error(rc)
+        ));
+    }
+    if result.is_null() {
+        return Err(format!("Unknown group: {}", name));
+    }
+    Ok(grp.gr_gid)
+}
+
+// 删除上次运行遗留的 socket 文件，但不抢占仍在使用中的 socket
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(io::Error::other(format!(
+            "{} exists and is not a socket",
+            path.display()
+        )));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::other(format!(
+            "{} is in use by another process",
+            path.display()
+        )));
+    }
+    fs::remove_file(path)
+}
+
+pub fn bind(config: &UnixSocketConfig) -> io::Result<Async<UnixListener>> {
+    let path = Path::new(&config.path);
+    remove_stale_socket(path)?;
+    let listener = Async::<UnixListener>::bind(path)?;
+
+    let mode = parse_mode(&config.mode)
+        .ok_or_else(|| io::Error::other(format!("Invalid socket mode: {}", config.mode)))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    let uid = match config.owner.as_str() {
+        "" => None,
+        owner => Some(lookup_user(owner).map_err(io::Error::other)?),
+    };
+    let gid = match config.group.as_str() {
+        "" => None,
+        group => Some(lookup_group(group).map_err(io::Error::other)?),
+    };
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0660"), Some(0o660));
+        assert_eq!(parse_mode("600"), Some(0o600));
+        assert_eq!(parse_mode("0778"), None);
+        assert_eq!(parse_mode("1777"), None);
+        assert_eq!(parse_mode(""), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup_user("root"), Ok(0));
+        assert_eq!(lookup_user("1234"), Ok(1234));
+        assert!(lookup_user("no-such-user-dn42").is_err());
+        assert_eq!(lookup_group("0"), Ok(0));
+    }
+
+    #[test]
+    fn test_peer_credentials() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let cred = peer_credentials(&a).unwrap();
+        assert_eq!(cred.pid, std::process::id() as i32);
+        // SAFETY: getuid has no preconditions
+        assert_eq!(cred.uid, unsafe { libc::getuid() });
+    }
+}